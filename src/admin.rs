@@ -3,8 +3,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     AppState,
-    auth::AuthUser,
-    types::{AppError, EmptyCreatedResponse, EmptyResponse, StockId},
+    auth::{AdminUser, AuthUser},
+    types::{
+        AppError, CircuitBreaker, EmptyCreatedResponse, EmptyResponse, FeeSchedule, StockId,
+        TradingPhase, TradingRules, Withdrawal, WithdrawalStatus, WithdrawalVec,
+    },
 };
 
 #[derive(Deserialize, Serialize)]
@@ -25,6 +28,96 @@ pub async fn add_money_to_wallet(
     Ok(EmptyCreatedResponse {})
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct WithdrawMoneyRequest {
+    pub amount: i64,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn withdraw_money_from_wallet(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Json(body): Json<WithdrawMoneyRequest>,
+) -> Result<Withdrawal, AppError> {
+    if body.amount <= 0 {
+        return Err(AppError::BadRequest);
+    }
+    let withdrawal = state.db.withdraw_money_from_user(user, body.amount).await?;
+    Ok(withdrawal)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_pending_withdrawals(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+) -> Result<WithdrawalVec, AppError> {
+    let out = state.db.get_withdrawals(None).await?;
+    Ok(WithdrawalVec(out))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawalDecisionRequest {
+    pub withdrawal_id: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn approve_withdrawal(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<WithdrawalDecisionRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let withdrawal_id = body
+        .withdrawal_id
+        .parse()
+        .map_err(|_| AppError::WithdrawalNotFound)?;
+    state
+        .db
+        .decide_withdrawal(withdrawal_id, user, WithdrawalStatus::Approved)
+        .await?;
+    Ok(EmptyResponse {})
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn reject_withdrawal(
+    AdminUser(user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<WithdrawalDecisionRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let withdrawal_id = body
+        .withdrawal_id
+        .parse()
+        .map_err(|_| AppError::WithdrawalNotFound)?;
+    state
+        .db
+        .decide_withdrawal(withdrawal_id, user, WithdrawalStatus::Rejected)
+        .await?;
+    Ok(EmptyResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdjustWalletRequest {
+    pub user_name: String,
+    /// Positive to credit the user, negative to debit them
+    pub amount: i64,
+    pub reason: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn adjust_wallet(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<AdjustWalletRequest>,
+) -> Result<EmptyResponse, AppError> {
+    if body.amount == 0 || body.reason.is_empty() {
+        return Err(AppError::BadRequest);
+    }
+    state
+        .db
+        .adjust_wallet(&body.user_name, body.amount, &body.reason)
+        .await?;
+    Ok(EmptyResponse {})
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AddStockToUserRequest {
     pub stock_id: String,
@@ -45,9 +138,11 @@ pub async fn add_stock_to_user(
     Ok(EmptyResponse {})
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct CreateStockRequest {
     pub stock_name: String,
+    #[serde(flatten)]
+    pub rules: TradingRules,
 }
 
 #[tracing::instrument(skip_all)]
@@ -56,7 +151,135 @@ pub async fn create_stock(
     State(state): State<AppState>,
     Json(body): Json<CreateStockRequest>,
 ) -> Result<StockId, AppError> {
-    let stock_id = state.db.create_stock(body.stock_name).await?.to_string();
+    if !body.rules.is_valid() {
+        return Err(AppError::BadRequest);
+    }
+    let stock_id = state
+        .db
+        .create_stock(body.stock_name, body.rules)
+        .await?
+        .to_string();
 
     Ok(StockId { stock_id })
 }
+
+/// Replaces all the trading rules of a stock, those left out go back to
+/// their defaults
+#[derive(Serialize, Deserialize)]
+pub struct UpdateStockRequest {
+    pub stock_id: String,
+    #[serde(flatten)]
+    pub rules: TradingRules,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn update_stock(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<UpdateStockRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let stock_id = body.stock_id.parse().map_err(|_| AppError::StockNotFound)?;
+    if !body.rules.is_valid() {
+        return Err(AppError::BadRequest);
+    }
+    state.db.set_trading_rules(stock_id, body.rules).await?;
+    Ok(EmptyResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetTradingPhaseRequest {
+    pub stock_id: String,
+    pub phase: TradingPhase,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn set_trading_phase(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<SetTradingPhaseRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let stock_id = body.stock_id.parse().map_err(|_| AppError::StockNotFound)?;
+    state.engine.set_trading_phase(stock_id, body.phase).await?;
+    Ok(EmptyResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TradingHaltRequest {
+    pub stock_id: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn halt_trading(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<TradingHaltRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let stock_id = body.stock_id.parse().map_err(|_| AppError::StockNotFound)?;
+    state.engine.halt_trading(stock_id).await?;
+    Ok(EmptyResponse {})
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn resume_trading(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<TradingHaltRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let stock_id = body.stock_id.parse().map_err(|_| AppError::StockNotFound)?;
+    state.engine.resume_trading(stock_id).await?;
+    Ok(EmptyResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetCircuitBreakerRequest {
+    pub stock_id: String,
+    /// Leaving the settings out turns the circuit breaker off
+    #[serde(flatten)]
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn set_circuit_breaker(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<SetCircuitBreakerRequest>,
+) -> Result<EmptyResponse, AppError> {
+    let stock_id = body.stock_id.parse().map_err(|_| AppError::StockNotFound)?;
+    if body.circuit_breaker.is_some_and(|b| !b.is_valid()) {
+        return Err(AppError::BadRequest);
+    }
+    state
+        .engine
+        .set_circuit_breaker(stock_id, body.circuit_breaker)
+        .await?;
+    Ok(EmptyResponse {})
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn set_fee_schedule(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<FeeSchedule>,
+) -> Result<EmptyResponse, AppError> {
+    if !body.is_valid() {
+        return Err(AppError::BadRequest);
+    }
+    state.db.set_fee_schedule(&body).await?;
+    Ok(EmptyResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetFeeTierRequest {
+    pub user_name: String,
+    pub tier: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn set_fee_tier(
+    AdminUser(_user): AdminUser,
+    State(state): State<AppState>,
+    Json(body): Json<SetFeeTierRequest>,
+) -> Result<EmptyResponse, AppError> {
+    state.db.set_fee_tier(&body.user_name, &body.tier).await?;
+    Ok(EmptyResponse {})
+}
@@ -5,7 +5,7 @@ use jsonwebtoken::{DecodingKey, Validation, decode};
 use serde::{Deserialize, Serialize};
 use tracing::{Instrument, info_span};
 
-use crate::types::AppError;
+use crate::types::{AppError, AppState};
 
 pub static SECRET: &str = "SECRET";
 
@@ -50,6 +50,25 @@ where
     }
 }
 
+/// An authenticated user allowed to use the admin endpoints
+pub struct AdminUser(pub i64);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let AuthUser(user) = AuthUser::from_request_parts(parts, state).await?;
+        if !state.db.is_admin(user).await? {
+            return Err(AppError::Forbidden);
+        }
+        Ok(AdminUser(user))
+    }
+}
+
 static TOKEN_HEADER: HeaderName = HeaderName::from_static("token");
 struct TokenHeader(String);
 impl Header for TokenHeader {
@@ -0,0 +1,992 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::types::{CircuitBreaker, SelfTradePrevention, TradingPhase};
+
+/// A resting order sitting in an [`OrderBook`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestingOrder {
+    pub order_id: i64,
+    pub user_id: i64,
+    pub remaining: i64,
+    /// Slice size of an iceberg order, `None` shows the whole order
+    pub display_quantity: Option<i64>,
+    /// Part of `remaining` that is shown and can trade before the order
+    /// goes to the back of the queue for a new slice
+    pub visible: i64,
+    /// Applies when the order meets another of its user's in an auction
+    pub self_trade_prevention: SelfTradePrevention,
+}
+
+impl RestingOrder {
+    /// An order that has already traded `filled`. Iceberg slices start at
+    /// every multiple of `display_quantity` filled, so the current one may
+    /// be partly used up.
+    pub fn new(
+        order_id: i64,
+        user_id: i64,
+        filled: i64,
+        remaining: i64,
+        display_quantity: Option<i64>,
+        self_trade_prevention: SelfTradePrevention,
+    ) -> Self {
+        RestingOrder {
+            order_id,
+            user_id,
+            remaining,
+            display_quantity,
+            visible: display_quantity.map_or(remaining, |d| (d - filled % d).min(remaining)),
+            self_trade_prevention,
+        }
+    }
+
+    /// Take `quantity` off the visible slice, returning whether the slice
+    /// was used up with more left in reserve and the order needs requeueing
+    fn fill(&mut self, quantity: i64) -> bool {
+        self.remaining -= quantity;
+        self.visible -= quantity;
+        if self.visible == 0 && self.remaining > 0 {
+            self.visible = self
+                .display_quantity
+                .map_or(self.remaining, |d| d.min(self.remaining));
+            return true;
+        }
+        false
+    }
+
+    /// Take `quantity` off the order one slice at a time, returning whether
+    /// it needs requeueing
+    fn fill_through(&mut self, mut quantity: i64) -> bool {
+        let mut refreshed = false;
+        while quantity > 0 {
+            let qty = quantity.min(self.visible);
+            quantity -= qty;
+            refreshed |= self.fill(qty);
+        }
+        refreshed && self.remaining > 0
+    }
+}
+
+/// A single execution between an incoming order and a resting order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    /// The resting (maker) order that was hit
+    pub order_id: i64,
+    pub user_id: i64,
+    pub price: i64,
+    pub quantity: i64,
+    /// Quantity left on the resting order after this fill
+    pub remaining: i64,
+    /// Whether this fill used up an iceberg slice and sent a new one to the
+    /// back of the queue
+    pub refreshed: bool,
+}
+
+/// Outcome of matching an incoming order against an [`OrderBook`]
+#[derive(Debug, Default, PartialEq)]
+pub struct Match {
+    pub fills: Vec<Fill>,
+    /// The incoming user's own resting orders cancelled by self-trade
+    /// prevention
+    pub cancelled: Vec<i64>,
+    /// Whether self-trade prevention cancelled whatever is left of the
+    /// incoming order
+    pub taker_cancelled: bool,
+    /// Changes made to the book, oldest first, to [`undo`] the match with
+    ///
+    /// [`undo`]: OrderBook::undo
+    steps: Vec<Step>,
+}
+
+/// A change matching made to a price level of the book
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// An order taken off the front of the level, as it was
+    Popped(i64, RestingOrder),
+    /// A partly filled order put back at the front of the level
+    Returned(i64),
+    /// A refreshed iceberg slice sent to the back of the level
+    Requeued(i64),
+}
+
+impl Match {
+    pub fn filled(&self) -> i64 {
+        self.fills.iter().map(|f| f.quantity).sum()
+    }
+}
+
+/// A single execution of an auction uncross
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cross {
+    pub buy_order: i64,
+    pub sell_order: i64,
+    pub quantity: i64,
+}
+
+/// Outcome of uncrossing an [`OrderBook`] at the end of a call phase
+#[derive(Debug, Default, PartialEq)]
+pub struct Uncross {
+    /// The single price everything executes at, meaningless without any
+    /// `volume`
+    pub price: i64,
+    pub volume: i64,
+    /// Demand left over at `price`, negative when supply is
+    pub imbalance: i64,
+    pub crosses: Vec<Cross>,
+    /// Iceberg orders that went through a slice and go to the back of
+    /// their level
+    pub refreshed: Vec<i64>,
+    /// Orders cancelled by self-trade prevention
+    pub cancelled: Vec<i64>,
+}
+
+/// A dormant stop order waiting for a trade at or through its trigger
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopOrder {
+    pub order_id: i64,
+    pub is_buy: bool,
+    pub trigger_price: i64,
+}
+
+type Levels = BTreeMap<i64, VecDeque<RestingOrder>>;
+
+/// In-memory price-time priority order book for a single stock, along with
+/// the stop orders that aren't on it yet
+#[derive(Debug, Default)]
+pub struct OrderBook {
+    bids: Levels,
+    asks: Levels,
+    stops: Vec<StopOrder>,
+    /// Orders only match as they arrive in the continuous phase, during a
+    /// call phase the book can be crossed until it is uncrossed
+    pub phase: TradingPhase,
+    /// Halted by an admin until resumed
+    pub halted: bool,
+    /// End of the cool-down after the circuit breaker tripped
+    pub halted_until: Option<DateTime<Utc>>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Prints within the circuit breaker's window, oldest first
+    recent_prices: VecDeque<(DateTime<Utc>, i64)>,
+}
+
+impl OrderBook {
+    fn side(&self, is_buy: bool) -> &Levels {
+        if is_buy { &self.bids } else { &self.asks }
+    }
+
+    fn side_mut(&mut self, is_buy: bool) -> &mut Levels {
+        if is_buy {
+            &mut self.bids
+        } else {
+            &mut self.asks
+        }
+    }
+
+    /// Add an order to the back of the queue at its price level
+    pub fn insert(&mut self, is_buy: bool, price: i64, order: RestingOrder) {
+        self.side_mut(is_buy)
+            .entry(price)
+            .or_default()
+            .push_back(order);
+    }
+
+    /// Side, price level and queue position of a resting order
+    fn locate(&self, order_id: i64) -> Option<(bool, i64, usize)> {
+        [true, false].into_iter().find_map(|is_buy| {
+            self.side(is_buy).iter().find_map(|(price, level)| {
+                level
+                    .iter()
+                    .position(|o| o.order_id == order_id)
+                    .map(|idx| (is_buy, *price, idx))
+            })
+        })
+    }
+
+    /// Look up a resting order along with its side and price
+    pub fn get(&self, order_id: i64) -> Option<(bool, i64, &RestingOrder)> {
+        let (is_buy, price, idx) = self.locate(order_id)?;
+        Some((is_buy, price, &self.side(is_buy)[&price][idx]))
+    }
+
+    /// Shrink a resting order in place, keeping its time priority
+    pub fn reduce(&mut self, order_id: i64, remaining: i64) {
+        let Some((is_buy, price, idx)) = self.locate(order_id) else {
+            return;
+        };
+        let order = &mut self
+            .side_mut(is_buy)
+            .get_mut(&price)
+            .expect("level to exist")[idx];
+        debug_assert!(remaining <= order.remaining);
+        order.remaining = remaining;
+        order.visible = order.visible.min(remaining);
+    }
+
+    /// Remove a resting order from the book, returning it if it was present
+    pub fn remove(&mut self, order_id: i64) -> Option<RestingOrder> {
+        let (is_buy, price, idx) = self.locate(order_id)?;
+
+        let levels = self.side_mut(is_buy);
+        let level = levels.get_mut(&price).expect("level to exist");
+        let order = level.remove(idx);
+        if level.is_empty() {
+            levels.remove(&price);
+        }
+        order
+    }
+
+    /// Park a stop order until a trade reaches its trigger
+    pub fn insert_stop(&mut self, stop: StopOrder) {
+        self.stops.push(stop);
+    }
+
+    /// Remove a dormant stop order, returning it if it was present
+    pub fn remove_stop(&mut self, order_id: i64) -> Option<StopOrder> {
+        let idx = self.stops.iter().position(|s| s.order_id == order_id)?;
+        Some(self.stops.remove(idx))
+    }
+
+    /// Take out the stops triggered by trades printed between `low` and
+    /// `high`: buy stops at or below `high` and sell stops at or above `low`,
+    /// oldest first
+    pub fn take_triggered(&mut self, low: i64, high: i64) -> Vec<StopOrder> {
+        let (triggered, dormant) = std::mem::take(&mut self.stops).into_iter().partition(|s| {
+            if s.is_buy {
+                s.trigger_price <= high
+            } else {
+                s.trigger_price >= low
+            }
+        });
+        self.stops = dormant;
+        triggered
+    }
+
+    /// Whether a limit order on `is_buy`'s side at `price` would trade on
+    /// arrival
+    pub fn crosses(&self, is_buy: bool, price: i64) -> bool {
+        if is_buy {
+            self.best_ask().is_some_and(|ask| ask <= price)
+        } else {
+            self.best_bid().is_some_and(|bid| bid >= price)
+        }
+    }
+
+    /// Match an incoming order of `quantity` by `user_id` against the other
+    /// side of the book in price-time priority, taking the fills off the
+    /// book as it goes. A `limit_price` stops the walk at the first level
+    /// that doesn't cross it; without one the order takes whatever is there.
+    /// If liquidity runs out the fills only cover part of the quantity.
+    /// Reaching one of the user's own orders is handled according to `stp`.
+    /// An iceberg order only trades its visible slice before going to the
+    /// back of its level, so one incoming order can hit it several times.
+    /// A match that can't go through is put back with [`undo`].
+    ///
+    /// [`undo`]: OrderBook::undo
+    pub fn match_order(
+        &mut self,
+        is_buy: bool,
+        user_id: i64,
+        quantity: i64,
+        limit_price: Option<i64>,
+        stp: SelfTradePrevention,
+    ) -> Match {
+        let mut matched = Match::default();
+        let mut left = quantity;
+        let levels = self.side_mut(!is_buy);
+
+        while left > 0 {
+            let best = if is_buy {
+                levels.first_entry()
+            } else {
+                levels.last_entry()
+            };
+            let Some(mut level) = best else { break };
+            let price = *level.key();
+            let crosses = match limit_price {
+                Some(limit) if is_buy => price <= limit,
+                Some(limit) => price >= limit,
+                None => true,
+            };
+            if !crosses {
+                break;
+            }
+
+            let queue = level.get_mut();
+            while left > 0
+                && let Some(mut order) = queue.pop_front()
+            {
+                if order.user_id == user_id {
+                    match stp {
+                        SelfTradePrevention::Allow => {}
+                        SelfTradePrevention::CancelOldest => {
+                            matched.steps.push(Step::Popped(price, order.clone()));
+                            matched.cancelled.push(order.order_id);
+                            continue;
+                        }
+                        SelfTradePrevention::CancelNewest => {
+                            queue.push_front(order);
+                            matched.taker_cancelled = true;
+                            break;
+                        }
+                        SelfTradePrevention::CancelBoth => {
+                            matched.steps.push(Step::Popped(price, order.clone()));
+                            matched.cancelled.push(order.order_id);
+                            matched.taker_cancelled = true;
+                            break;
+                        }
+                    }
+                }
+                matched.steps.push(Step::Popped(price, order.clone()));
+                let qty = left.min(order.visible);
+                left -= qty;
+                let refreshed = order.fill(qty);
+                matched.fills.push(Fill {
+                    order_id: order.order_id,
+                    user_id: order.user_id,
+                    price,
+                    quantity: qty,
+                    remaining: order.remaining,
+                    refreshed,
+                });
+                if refreshed {
+                    queue.push_back(order);
+                    matched.steps.push(Step::Requeued(price));
+                } else if order.remaining > 0 {
+                    queue.push_front(order);
+                    matched.steps.push(Step::Returned(price));
+                }
+            }
+            if queue.is_empty() {
+                level.remove();
+            }
+            if matched.taker_cancelled {
+                break;
+            }
+        }
+
+        matched
+    }
+
+    /// Put back everything a match for an incoming order on `is_buy`'s side
+    /// took off the book, leaving it as it was before
+    pub fn undo(&mut self, is_buy: bool, matched: &Match) {
+        let levels = self.side_mut(!is_buy);
+        for step in matched.steps.iter().rev() {
+            match step {
+                Step::Popped(price, order) => {
+                    levels.entry(*price).or_default().push_front(order.clone());
+                }
+                Step::Returned(price) => {
+                    levels.get_mut(price).and_then(|l| l.pop_front());
+                }
+                Step::Requeued(price) => {
+                    levels.get_mut(price).and_then(|l| l.pop_back());
+                }
+            }
+        }
+        levels.retain(|_, level| !level.is_empty());
+    }
+
+    /// Work out the single-price auction for whatever crosses on the book:
+    /// the price executing the most volume, then leaving the smallest
+    /// imbalance, then closest to `reference` and finally the lowest. Bids
+    /// and asks are paired off in price-time priority, icebergs with their
+    /// whole quantity. A pair of the same user's orders is handled according
+    /// to the mode of the one placed last, as if it were the incoming order,
+    /// and the auction worked out again without the orders that cancels.
+    /// The book is left untouched until the uncross is [`apply_uncross`]'d.
+    /// If self-trade prevention leaves nothing to cross, the uncross only
+    /// cancels.
+    ///
+    /// [`apply_uncross`]: OrderBook::apply_uncross
+    pub fn uncross(&self, reference: Option<i64>) -> Option<Uncross> {
+        let mut cancelled = vec![];
+        loop {
+            match self.pair_off(reference, &cancelled) {
+                Ok(Some(uncross)) => {
+                    return Some(Uncross {
+                        cancelled,
+                        ..uncross
+                    });
+                }
+                Ok(None) if cancelled.is_empty() => return None,
+                Ok(None) => {
+                    return Some(Uncross {
+                        cancelled,
+                        ..Default::default()
+                    });
+                }
+                Err(more) => cancelled.extend(more),
+            }
+        }
+    }
+
+    /// Uncross the orders left once `cancelled` are gone, or stop at the
+    /// first pair of the same user's orders with the ones self-trade
+    /// prevention cancels
+    fn pair_off(
+        &self,
+        reference: Option<i64>,
+        cancelled: &[i64],
+    ) -> Result<Option<Uncross>, Vec<i64>> {
+        let open = |o: &&RestingOrder| !cancelled.contains(&o.order_id);
+        let quantity = |orders: &VecDeque<RestingOrder>| {
+            orders.iter().filter(open).map(|o| o.remaining).sum::<i64>()
+        };
+        let Some((price, volume, imbalance)) = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .filter(|(_, l)| quantity(l) > 0)
+            .map(|(price, _)| *price)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|price| {
+                let demand: i64 = self.bids.range(price..).map(|(_, l)| quantity(l)).sum();
+                let supply: i64 = self.asks.range(..=price).map(|(_, l)| quantity(l)).sum();
+                (price, demand.min(supply), demand - supply)
+            })
+            .filter(|(_, volume, _)| *volume > 0)
+            .min_by_key(|(price, volume, imbalance)| {
+                (
+                    Reverse(*volume),
+                    imbalance.abs(),
+                    reference.map_or(0, |r| (price - r).abs()),
+                    *price,
+                )
+            })
+        else {
+            return Ok(None);
+        };
+
+        let mut bids = self
+            .bids
+            .range(price..)
+            .rev()
+            .flat_map(|(_, l)| l.iter().filter(open).cloned())
+            .collect::<Vec<_>>();
+        let mut asks = self
+            .asks
+            .range(..=price)
+            .flat_map(|(_, l)| l.iter().filter(open).cloned())
+            .collect::<Vec<_>>();
+        let mut uncross = Uncross {
+            price,
+            volume,
+            imbalance,
+            ..Default::default()
+        };
+        let (mut b, mut a, mut left) = (0, 0, volume);
+        while left > 0 {
+            let (bid, ask) = (&mut bids[b], &mut asks[a]);
+            if bid.user_id == ask.user_id {
+                let (older, newer) = if bid.order_id < ask.order_id {
+                    (bid.order_id, &*ask)
+                } else {
+                    (ask.order_id, &*bid)
+                };
+                match newer.self_trade_prevention {
+                    SelfTradePrevention::Allow => {}
+                    SelfTradePrevention::CancelOldest => return Err(vec![older]),
+                    SelfTradePrevention::CancelNewest => return Err(vec![newer.order_id]),
+                    SelfTradePrevention::CancelBoth => {
+                        return Err(vec![older, newer.order_id]);
+                    }
+                }
+            }
+            let qty = left.min(bid.remaining).min(ask.remaining);
+            left -= qty;
+            for order in [&mut *bid, &mut *ask] {
+                if order.fill_through(qty) && !uncross.refreshed.contains(&order.order_id) {
+                    uncross.refreshed.push(order.order_id);
+                }
+            }
+            uncross.crosses.push(Cross {
+                buy_order: bid.order_id,
+                sell_order: ask.order_id,
+                quantity: qty,
+            });
+            if bid.remaining == 0 {
+                b += 1;
+            }
+            if ask.remaining == 0 {
+                a += 1;
+            }
+        }
+        uncross.refreshed.retain(|id| {
+            bids.iter()
+                .chain(asks.iter())
+                .any(|o| o.order_id == *id && o.remaining > 0)
+        });
+
+        Ok(Some(uncross))
+    }
+
+    /// Commit an uncross, removing cancelled and exhausted orders and
+    /// requeueing refreshed iceberg slices
+    pub fn apply_uncross(&mut self, uncross: &Uncross) {
+        for order_id in &uncross.cancelled {
+            self.remove(*order_id);
+        }
+        for cross in &uncross.crosses {
+            for order_id in [cross.buy_order, cross.sell_order] {
+                let Some((is_buy, price, idx)) = self.locate(order_id) else {
+                    continue;
+                };
+                let levels = self.side_mut(is_buy);
+                let level = levels.get_mut(&price).expect("level to exist");
+                if level[idx].fill_through(cross.quantity) {
+                    let order = level.remove(idx).expect("order to be in its level");
+                    level.push_back(order);
+                }
+                level.retain(|o| o.remaining > 0);
+                if level.is_empty() {
+                    levels.remove(&price);
+                }
+            }
+        }
+    }
+
+    /// Whether orders can't be placed or amended at `now`
+    pub fn is_halted(&self, now: DateTime<Utc>) -> bool {
+        self.halted || self.halted_until.is_some_and(|until| until > now)
+    }
+
+    /// Feed the circuit breaker the prices traded at `now`. If they take
+    /// the stock too far within its window the book is halted and the end
+    /// of the cool-down returned.
+    pub fn trip_circuit_breaker(
+        &mut self,
+        now: DateTime<Utc>,
+        prices: &[i64],
+    ) -> Option<DateTime<Utc>> {
+        let Some(breaker) = self.circuit_breaker else {
+            self.recent_prices.clear();
+            return None;
+        };
+        let window_start = now - TimeDelta::seconds(breaker.window_secs);
+        while self
+            .recent_prices
+            .front()
+            .is_some_and(|(at, _)| *at < window_start)
+        {
+            self.recent_prices.pop_front();
+        }
+        self.recent_prices
+            .extend(prices.iter().map(|price| (now, *price)));
+
+        let low = self.recent_prices.iter().map(|(_, p)| *p).min()?;
+        let high = self.recent_prices.iter().map(|(_, p)| *p).max()?;
+        if (high - low) * 100 <= breaker.max_move_percent * low {
+            return None;
+        }
+        // The cool-down starts a fresh window
+        self.recent_prices.clear();
+        let until = now + TimeDelta::seconds(breaker.halt_secs);
+        self.halted_until = Some(until);
+        Some(until)
+    }
+
+    /// Highest resting bid, if any
+    pub fn best_bid(&self) -> Option<i64> {
+        self.bids.keys().next_back().copied()
+    }
+
+    /// Lowest resting ask, if any
+    pub fn best_ask(&self) -> Option<i64> {
+        self.asks.keys().next().copied()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::{Fill, Match, OrderBook, RestingOrder, StopOrder};
+    use crate::types::{
+        CircuitBreaker,
+        SelfTradePrevention::{self, Allow, CancelBoth, CancelNewest, CancelOldest},
+    };
+
+    fn order(order_id: i64, user_id: i64, remaining: i64) -> RestingOrder {
+        RestingOrder::new(order_id, user_id, 0, remaining, None, CancelNewest)
+    }
+
+    #[test]
+    fn test_market_buy_price_time_priority() {
+        let mut book = OrderBook::default();
+        book.insert(false, 135, order(1, 1, 10));
+        book.insert(false, 130, order(2, 1, 5));
+        book.insert(false, 130, order(3, 2, 5));
+        book.insert(false, 130, order(4, 3, 5));
+
+        let matched = book.match_order(true, 9, 12, None, CancelNewest);
+        assert_eq!(
+            matched.fills,
+            vec![
+                Fill {
+                    order_id: 2,
+                    user_id: 1,
+                    price: 130,
+                    quantity: 5,
+                    remaining: 0,
+                    refreshed: false
+                },
+                Fill {
+                    order_id: 3,
+                    user_id: 2,
+                    price: 130,
+                    quantity: 5,
+                    remaining: 0,
+                    refreshed: false
+                },
+                Fill {
+                    order_id: 4,
+                    user_id: 3,
+                    price: 130,
+                    quantity: 2,
+                    remaining: 3,
+                    refreshed: false
+                },
+            ]
+        );
+
+        assert_eq!(book.best_ask(), Some(130));
+        assert_eq!(book.remove(4), Some(order(4, 3, 3)));
+        assert_eq!(book.best_ask(), Some(135));
+    }
+
+    #[test]
+    fn test_market_buy_runs_out_of_liquidity() {
+        let mut book = OrderBook::default();
+        book.insert(false, 100, order(1, 7, 10));
+        book.insert(false, 110, order(2, 8, 3));
+
+        assert_eq!(
+            book.match_order(true, 9, 15, None, CancelNewest).filled(),
+            13
+        );
+        assert_eq!(
+            book.match_order(true, 8, 0, None, CancelNewest),
+            Match::default()
+        );
+    }
+
+    #[test]
+    fn test_self_trade_prevention_modes() {
+        let book = || {
+            let mut book = OrderBook::default();
+            book.insert(false, 100, order(1, 8, 2));
+            book.insert(false, 100, order(2, 7, 10));
+            book.insert(false, 110, order(3, 8, 3));
+            book
+        };
+
+        let fills = |m: &Match| {
+            m.fills
+                .iter()
+                .map(|f| (f.order_id, f.quantity))
+                .collect::<Vec<_>>()
+        };
+        let run = |stp: SelfTradePrevention| book().match_order(true, 7, 12, None, stp);
+
+        let matched = run(Allow);
+        assert_eq!(fills(&matched), vec![(1, 2), (2, 10)]);
+        assert_eq!(
+            (matched.cancelled, matched.taker_cancelled),
+            (vec![], false)
+        );
+
+        let matched = run(CancelOldest);
+        assert_eq!(fills(&matched), vec![(1, 2), (3, 3)]);
+        assert_eq!(
+            (matched.cancelled, matched.taker_cancelled),
+            (vec![2], false)
+        );
+
+        let matched = run(CancelNewest);
+        assert_eq!(fills(&matched), vec![(1, 2)]);
+        assert_eq!((matched.cancelled, matched.taker_cancelled), (vec![], true));
+
+        let mut book = book();
+        let matched = book.match_order(true, 7, 12, None, CancelBoth);
+        assert_eq!(fills(&matched), vec![(1, 2)]);
+        assert_eq!(
+            (matched.cancelled.clone(), matched.taker_cancelled),
+            (vec![2], true)
+        );
+        assert_eq!(book.get(2), None);
+        assert_eq!(book.best_ask(), Some(110));
+    }
+
+    #[test]
+    fn test_limit_orders_only_cross_at_their_price() {
+        let mut book = OrderBook::default();
+        book.insert(true, 95, order(1, 1, 10));
+        book.insert(true, 100, order(2, 2, 10));
+        book.insert(false, 105, order(3, 3, 10));
+        assert_eq!((book.best_bid(), book.best_ask()), (Some(100), Some(105)));
+
+        // Limit buy below the best ask rests without trading
+        assert_eq!(
+            book.match_order(true, 4, 5, Some(104), CancelNewest),
+            Match::default()
+        );
+
+        // Limit sell walks the bids from the highest down to its limit
+        let matched = book.match_order(false, 4, 25, Some(96), CancelNewest);
+        assert_eq!(matched.fills.len(), 1);
+        book.undo(false, &matched);
+        let matched = book.match_order(false, 4, 25, Some(95), CancelNewest);
+        assert_eq!(
+            matched
+                .fills
+                .iter()
+                .map(|f| (f.price, f.quantity))
+                .collect::<Vec<_>>(),
+            vec![(100, 10), (95, 10)]
+        );
+        book.undo(false, &matched);
+
+        // Market sell hits the best bid
+        book.match_order(false, 4, 12, None, CancelNewest);
+        assert_eq!(book.best_bid(), Some(95));
+        assert_eq!(book.remove(1), Some(order(1, 1, 8)));
+        assert_eq!(book.best_bid(), None);
+    }
+
+    #[test]
+    fn test_reduce_keeps_queue_position() {
+        let mut book = OrderBook::default();
+        book.insert(false, 100, order(1, 1, 10));
+        book.insert(false, 100, order(2, 2, 10));
+        book.reduce(1, 4);
+        assert_eq!(book.get(1), Some((false, 100, &order(1, 1, 4))));
+
+        let matched = book.match_order(true, 3, 6, None, CancelNewest);
+        assert_eq!(
+            matched
+                .fills
+                .iter()
+                .map(|f| (f.order_id, f.quantity))
+                .collect::<Vec<_>>(),
+            vec![(1, 4), (2, 2)]
+        );
+    }
+
+    #[test]
+    fn test_iceberg_refreshes_to_back_of_level() {
+        let mut book = OrderBook::default();
+        book.insert(
+            false,
+            100,
+            RestingOrder::new(1, 1, 0, 10, Some(3), CancelNewest),
+        );
+        book.insert(false, 100, order(2, 2, 2));
+        book.insert(false, 101, order(3, 3, 5));
+
+        let matched = book.match_order(true, 9, 9, None, CancelNewest);
+        assert_eq!(
+            matched
+                .fills
+                .iter()
+                .map(|f| (f.order_id, f.quantity, f.remaining, f.refreshed))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, 3, 7, true),
+                (2, 2, 0, false),
+                (1, 3, 4, true),
+                (1, 1, 3, false)
+            ]
+        );
+
+        assert_eq!(
+            book.get(1),
+            Some((
+                false,
+                100,
+                &RestingOrder {
+                    order_id: 1,
+                    user_id: 1,
+                    remaining: 3,
+                    display_quantity: Some(3),
+                    visible: 2,
+                    self_trade_prevention: CancelNewest
+                }
+            ))
+        );
+
+        // Resting after trading part of a slice leaves the rest of it
+        assert_eq!(
+            RestingOrder::new(4, 1, 5, 10, Some(3), CancelNewest).visible,
+            1
+        );
+
+        // Shrinking below the visible slice shrinks the slice too
+        book.reduce(1, 1);
+        assert_eq!(book.get(1).map(|(_, _, o)| o.visible), Some(1));
+    }
+
+    #[test]
+    fn test_undo_puts_the_book_back() {
+        let mut book = OrderBook::default();
+        book.insert(
+            false,
+            100,
+            RestingOrder::new(1, 1, 0, 10, Some(3), CancelNewest),
+        );
+        book.insert(false, 100, order(2, 9, 2));
+        book.insert(false, 100, order(3, 2, 4));
+        book.insert(false, 101, order(4, 3, 5));
+        let levels = book.asks.clone();
+
+        // Iceberg refreshes, an own order cancelled and a partial fill
+        let matched = book.match_order(true, 9, 9, None, SelfTradePrevention::CancelOldest);
+        assert_eq!(matched.cancelled, vec![2]);
+        assert_eq!(matched.filled(), 9);
+        book.undo(true, &matched);
+        assert_eq!(book.asks, levels);
+
+        // A level walked through entirely
+        let matched = book.match_order(true, 8, 20, None, SelfTradePrevention::CancelOldest);
+        assert_eq!(book.best_ask(), Some(101));
+        book.undo(true, &matched);
+        assert_eq!(book.asks, levels);
+    }
+
+    #[test]
+    fn test_uncross_maximises_volume_at_one_price() {
+        let mut book = OrderBook::default();
+        book.insert(true, 102, order(1, 1, 5));
+        book.insert(
+            true,
+            100,
+            RestingOrder::new(2, 2, 0, 10, Some(4), CancelNewest),
+        );
+        book.insert(true, 98, order(3, 3, 5));
+        book.insert(false, 97, order(4, 4, 4));
+        book.insert(false, 99, order(5, 5, 6));
+        book.insert(false, 101, order(6, 6, 10));
+
+        // 99 and 100 both execute 10 leaving 5 bid, the reference decides
+        assert_eq!(book.uncross(Some(101)).map(|u| u.price), Some(100));
+        let uncross = book.uncross(None).unwrap();
+        assert_eq!(
+            (uncross.price, uncross.volume, uncross.imbalance),
+            (99, 10, 5)
+        );
+        assert_eq!(
+            uncross
+                .crosses
+                .iter()
+                .map(|c| (c.buy_order, c.sell_order, c.quantity))
+                .collect::<Vec<_>>(),
+            vec![(1, 4, 4), (1, 5, 1), (2, 5, 5)]
+        );
+        assert_eq!(uncross.refreshed, vec![2]);
+
+        book.apply_uncross(&uncross);
+        assert_eq!((book.best_bid(), book.best_ask()), (Some(100), Some(101)));
+        assert_eq!(
+            book.get(2).map(|(_, _, o)| (o.remaining, o.visible)),
+            Some((5, 3))
+        );
+        assert_eq!(book.uncross(None), None);
+    }
+
+    #[test]
+    fn test_uncross_self_trade_prevention() {
+        // User 1 bids 5 at 100 and later offers 5 at 98 under `stp`, user 2
+        // offers 5 at 99
+        let book = |stp| {
+            let mut book = OrderBook::default();
+            book.insert(true, 100, order(1, 1, 5));
+            book.insert(false, 99, order(2, 2, 5));
+            book.insert(
+                false,
+                98,
+                RestingOrder {
+                    self_trade_prevention: stp,
+                    ..order(3, 1, 5)
+                },
+            );
+            book
+        };
+        let run = |stp| {
+            book(stp).uncross(None).map(|u| {
+                (
+                    u.price,
+                    u.crosses
+                        .iter()
+                        .map(|c| (c.buy_order, c.sell_order, c.quantity))
+                        .collect::<Vec<_>>(),
+                    u.cancelled,
+                )
+            })
+        };
+
+        assert_eq!(run(Allow), Some((98, vec![(1, 3, 5)], vec![])));
+        // The offer cancels and the bid crosses user 2 instead
+        assert_eq!(run(CancelNewest), Some((99, vec![(1, 2, 5)], vec![3])));
+        // Without the bid nothing crosses, the uncross only cancels
+        assert_eq!(run(CancelOldest), Some((0, vec![], vec![1])));
+        assert_eq!(run(CancelBoth), Some((0, vec![], vec![1, 3])));
+
+        let mut book = book(CancelNewest);
+        let uncross = book.uncross(None).unwrap();
+        book.apply_uncross(&uncross);
+        assert_eq!((book.best_bid(), book.best_ask()), (None, None));
+        assert_eq!(book.get(3), None);
+    }
+
+    #[test]
+    fn test_circuit_breaker_trips_on_moves_within_its_window() {
+        let start = Utc::now();
+        let at = |secs| start + TimeDelta::seconds(secs);
+        let mut book = OrderBook {
+            circuit_breaker: Some(CircuitBreaker {
+                max_move_percent: 10,
+                window_secs: 60,
+                halt_secs: 300,
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(book.trip_circuit_breaker(at(0), &[100, 105]), None);
+        assert_eq!(book.trip_circuit_breaker(at(30), &[110]), None);
+        // 100 has left the window by the time 111 prints
+        assert_eq!(book.trip_circuit_breaker(at(61), &[111]), None);
+        assert!(!book.is_halted(at(61)));
+        assert_eq!(book.trip_circuit_breaker(at(62), &[94]), Some(at(362)));
+        assert!(book.is_halted(at(361)));
+        assert!(!book.is_halted(at(362)));
+    }
+
+    #[test]
+    fn test_stops_trigger_at_or_through_their_price() {
+        let stop = |order_id, is_buy, trigger_price| StopOrder {
+            order_id,
+            is_buy,
+            trigger_price,
+        };
+        let mut book = OrderBook::default();
+        book.insert_stop(stop(1, true, 110));
+        book.insert_stop(stop(2, false, 90));
+        book.insert_stop(stop(3, true, 105));
+        book.insert_stop(stop(4, false, 95));
+
+        assert_eq!(book.take_triggered(96, 104), vec![]);
+        assert_eq!(book.take_triggered(100, 105), vec![stop(3, true, 105)]);
+        assert_eq!(
+            book.take_triggered(90, 100),
+            vec![stop(2, false, 90), stop(4, false, 95)]
+        );
+        assert_eq!(book.remove_stop(1), Some(stop(1, true, 110)));
+        assert_eq!(book.take_triggered(0, 1000), vec![]);
+    }
+}
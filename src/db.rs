@@ -1,13 +1,21 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use bigdecimal::{BigDecimal, ToPrimitive};
-use chrono::NaiveDateTime;
-use sqlx::{PgPool, postgres::PgPoolOptions};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{PgExecutor, PgPool, postgres::PgPoolOptions};
 use tracing::error;
 
-use crate::types::{
-    AppError, OrderStatus, OrderType, StockPortfolio, StockPrice, StockTransaction,
-    WalletTransaction,
+use crate::{
+    book::{Match, Uncross},
+    engine::{NewGroup, NewOrder},
+    portfolio,
+    types::{
+        AccountType, AppError, Balance, Candle, CandleInterval, CircuitBreaker, EntryType,
+        ExpiryReason, FeeSchedule, HistoryFilter, LotMethod, MarketTrade, OrderAmendment,
+        OrderBookDepth, OrderGroupType, OrderStatus, OrderType, PortfolioSummary, PriceLevel,
+        SelfTradePrevention, Side, StockPortfolio, StockPrice, StockTransaction, TimeInForce,
+        TradingPhase, TradingRules, WalletTransaction, Withdrawal, WithdrawalStatus,
+    },
 };
 
 pub type DbPool = PgPool;
@@ -15,6 +23,12 @@ pub type DbPool = PgPool;
 #[derive(Clone)]
 pub struct DB {
     pool: DbPool,
+    /// Compatibility mode for the TA provided test suite, which cancels
+    /// orders on behalf of other users. Enabled with `CANCEL_ANY_ORDER=true`
+    cancel_any_order: bool,
+    /// Withdrawals above this wait for an admin to approve them. Set with
+    /// `WITHDRAWAL_APPROVAL_THRESHOLD`
+    withdrawal_approval_threshold: i64,
 }
 
 impl DB {
@@ -26,11 +40,24 @@ impl DB {
                 .connect(std::env::var("DB_ENDPOINT").unwrap().as_str())
                 .await
                 .unwrap(),
+            cancel_any_order: std::env::var("CANCEL_ANY_ORDER").is_ok_and(|v| v == "true"),
+            withdrawal_approval_threshold: std::env::var("WITHDRAWAL_APPROVAL_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10_000),
         };
 
         Ok(db)
     }
 
+    /// Lets any user cancel any order, as `CANCEL_ANY_ORDER=true` does
+    pub fn with_cancel_any_order(self, cancel_any_order: bool) -> Self {
+        DB {
+            cancel_any_order,
+            ..self
+        }
+    }
+
     pub async fn healthcheck(&self) -> Result<(), ()> {
         let row: (i64,) = sqlx::query_as("SELECT 1")
             .fetch_one(&self.pool)
@@ -42,9 +69,15 @@ impl DB {
     #[tracing::instrument(skip(self, password), fields(service.name = "db", db.operation.name = "INSERT"))]
     pub async fn create_user(&self, user_name: String, password: String) -> Result<(), AppError> {
         let res = sqlx::query!(
-            "INSERT INTO users (user_name, password) VALUES ($1, $2)",
+            r#"
+            WITH u AS (
+                INSERT INTO users (user_name, password) VALUES ($1, $2) RETURNING user_id
+            )
+            INSERT INTO ledger_accounts (user_id, account_type) SELECT user_id, $3 FROM u
+            "#,
             user_name,
-            password
+            password,
+            AccountType::Cash as i64
         )
         .execute(&self.pool)
         .await
@@ -82,12 +115,59 @@ impl DB {
         Ok(row)
     }
 
+    /// Whether a user can use the admin endpoints, false for a user that
+    /// doesn't exist
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn is_admin(&self, user_id: i64) -> Result<bool, AppError> {
+        let is_admin =
+            sqlx::query_scalar!("SELECT is_admin FROM users WHERE user_id = $1", user_id)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| {
+                    error!(user_id, "{}", &e);
+                    AppError::DatabaseError
+                })?;
+
+        Ok(is_admin.unwrap_or(false))
+    }
+
     #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
     pub async fn add_money_to_user(&self, user_id: i64, amount: i64) -> Result<(), AppError> {
+        // The deposit and its journal entry are written in one statement
         let _row = sqlx::query!(
-            "INSERT INTO deposits (user_id, amount) VALUES ($1, $2)",
+            r#"
+            WITH d AS (
+                INSERT INTO deposits (user_id, amount) VALUES ($1, $2) RETURNING deposit_id
+            ),
+            e AS (
+                INSERT INTO journal_entries (entry_type, deposit_id)
+                SELECT $3, deposit_id FROM d
+                RETURNING entry_id
+            ),
+            p AS (
+                INSERT INTO postings (entry_id, account_id, amount)
+                SELECT e.entry_id, x.account_id, x.amount
+                FROM e
+                CROSS JOIN LATERAL (VALUES
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id = $1), $2),
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id IS NULL AND a.account_type = $4), -$2)
+                ) AS x(account_id, amount)
+                RETURNING account_id, amount
+            ),
+            locked AS (
+                SELECT a.account_id FROM ledger_accounts a
+                WHERE a.account_id IN (SELECT account_id FROM p) AND a.user_id IS NOT NULL
+                ORDER BY a.account_id
+                FOR UPDATE
+            )
+            UPDATE ledger_accounts a SET balance = a.balance + p.total
+            FROM (SELECT account_id, SUM(amount) AS total FROM p GROUP BY account_id) p
+            WHERE a.account_id = p.account_id AND a.account_id IN (SELECT account_id FROM locked)
+            "#,
             user_id,
-            amount
+            amount,
+            EntryType::Deposit as i64,
+            AccountType::Funding as i64
         )
         .execute(&self.pool)
         .await
@@ -103,10 +183,18 @@ impl DB {
     }
 
     #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
-    pub async fn create_stock(&self, stock_name: String) -> Result<i64, AppError> {
+    pub async fn create_stock(
+        &self,
+        stock_name: String,
+        rules: TradingRules,
+    ) -> Result<i64, AppError> {
         let stock_id = sqlx::query!(
-            "INSERT INTO stocks (stock_name) VALUES ($1) RETURNING stock_id",
-            stock_name
+            "INSERT INTO stocks (stock_name, tick_size, lot_size, max_order_quantity, price_collar_percent) VALUES ($1, $2, $3, $4, $5) RETURNING stock_id",
+            stock_name,
+            rules.tick_size,
+            rules.lot_size,
+            rules.max_order_quantity,
+            rules.price_collar_percent
         )
         .fetch_one(&self.pool)
         .await
@@ -119,6 +207,51 @@ impl DB {
         Ok(stock_id)
     }
 
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_trading_rules(&self, stock_id: i64) -> Result<TradingRules, AppError> {
+        let rules = sqlx::query_as!(
+            TradingRules,
+            "SELECT tick_size, lot_size, max_order_quantity, price_collar_percent FROM stocks WHERE stock_id = $1",
+            stock_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?;
+
+        Ok(rules)
+    }
+
+    /// Replace the trading rules of a stock, orders already placed aren't
+    /// checked against them again
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn set_trading_rules(
+        &self,
+        stock_id: i64,
+        rules: TradingRules,
+    ) -> Result<(), AppError> {
+        let _ = sqlx::query!(
+            "UPDATE stocks SET tick_size = $1, lot_size = $2, max_order_quantity = $3, price_collar_percent = $4 WHERE stock_id = $5 RETURNING stock_id",
+            rules.tick_size,
+            rules.lot_size,
+            rules.max_order_quantity,
+            rules.price_collar_percent,
+            stock_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?;
+
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
     pub async fn add_stock_to_user(
         &self,
@@ -127,10 +260,10 @@ impl DB {
         quantity: i64,
     ) -> Result<(), AppError> {
         let order_ids = sqlx::query!(r#"
-            INSERT INTO orders (user_id, stock_id, amount, limit_price, order_status, created_at) VALUES (1, $1, $2, 0, $3, '0001-01-01 00:00:00'), ($4, $5, $6, NULL, $7, '0001-01-01 00:00:00') RETURNING order_id"#,
-            stock_id, quantity, OrderStatus::Completed as i64,
+            INSERT INTO orders (user_id, stock_id, is_buy, order_type, amount, limit_price, order_status, time_in_force, created_at) VALUES (1, $1, FALSE, $2, $3, 0, $4, $5, '0001-01-01 00:00:00'), ($6, $7, TRUE, $8, $9, NULL, $10, $11, '0001-01-01 00:00:00') RETURNING order_id"#,
+            stock_id, OrderType::Limit as i64, quantity, OrderStatus::Completed as i64, TimeInForce::GoodTilCancelled as i64,
             //
-            user_id, stock_id, quantity, OrderStatus::Completed as i64,
+            user_id, stock_id, OrderType::Market as i64, quantity, OrderStatus::Completed as i64, TimeInForce::ImmediateOrCancel as i64,
         )
         .fetch_all(&self.pool)
         .await
@@ -140,8 +273,8 @@ impl DB {
         })?;
 
         let _ = sqlx::query!(r#"
-            INSERT INTO trades (sell_order, buy_order, amount, created_at) VALUES ($1, $2, $3, '0001-01-01 00:00:00')"#,
-             order_ids[0].order_id, order_ids[1].order_id, quantity
+            INSERT INTO trades (stock_id, sell_order, buy_order, amount, price, created_at) VALUES ($1, $2, $3, $4, 0, '0001-01-01 00:00:00')"#,
+             stock_id, order_ids[0].order_id, order_ids[1].order_id, quantity
         )
         .execute(&self.pool)
         .await
@@ -153,29 +286,48 @@ impl DB {
         Ok(())
     }
 
+    /// Quote and last trade summary of every stock, traded or not. Setup
+    /// transfers from `add_stock_to_user` don't count as trades.
     #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
     pub async fn get_stock_prices(&self) -> Result<Vec<StockPrice>, AppError> {
         let data = sqlx::query_as!(
             DBStockPrice,
             r#"
-            SELECT s.stock_id AS "stock_id!", s.stock_name AS "stock_name!", MIN(o.limit_price) AS price
+            SELECT s.stock_id, s.stock_name,
+                (SELECT MAX(o.limit_price) FROM orders o WHERE o.stock_id = s.stock_id AND o.is_buy AND o.order_type = $1 AND o.order_status IN ($2, $3)) AS best_bid,
+                (SELECT MIN(o.limit_price) FROM orders o WHERE o.stock_id = s.stock_id AND NOT o.is_buy AND o.order_type = $1 AND o.order_status IN ($2, $3)) AS best_ask,
+                (SELECT t.price FROM trades t WHERE t.stock_id = s.stock_id AND t.seq IS NOT NULL ORDER BY t.seq DESC LIMIT 1) AS last_price,
+                (SELECT c.price FROM (
+                    (SELECT a.price, a.created_at, TRUE AS is_auction FROM auctions a WHERE a.stock_id = s.stock_id AND a.phase = $4 AND a.created_at < date_trunc('day', LOCALTIMESTAMP) ORDER BY a.auction_id DESC LIMIT 1)
+                    UNION ALL
+                    (SELECT t.price, t.created_at, FALSE AS is_auction FROM trades t WHERE t.stock_id = s.stock_id AND t.seq IS NOT NULL AND t.created_at < date_trunc('day', LOCALTIMESTAMP) ORDER BY t.seq DESC LIMIT 1)
+                ) c ORDER BY c.created_at DESC, c.is_auction DESC LIMIT 1) AS previous_close,
+                (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.stock_id = s.stock_id AND t.seq IS NOT NULL AND t.created_at >= LOCALTIMESTAMP - INTERVAL '24 hours')::bigint AS "volume_24h!",
+                (s.halted OR COALESCE(s.halted_until > $5, FALSE)) AS "trading_halted!"
             FROM stocks s
-            JOIN orders o ON s.stock_id = o.stock_id
-            WHERE o.limit_price IS NOT NULL AND o.order_status IN ($1, $2)
-            GROUP BY s.stock_id, s.stock_name
             ORDER BY s.stock_name DESC
            "#,
+            OrderType::Limit as i64,
             OrderStatus::InProgress as i64,
-            OrderStatus::PartiallyComplete as i64
+            OrderStatus::PartiallyComplete as i64,
+            TradingPhase::PreClose as i64,
+            Utc::now().naive_utc()
         )
         .fetch_all(&self.pool)
         .await
         .map(|p| {
-            p.iter()
+            p.into_iter()
                 .map(|i| StockPrice {
                     stock_id: i.stock_id.to_string(),
-                    stock_name: i.stock_name.clone(),
-                    current_price: i.price.unwrap_or(0),
+                    stock_name: i.stock_name,
+                    current_price: i.best_ask.unwrap_or(0),
+                    last_price: i.last_price,
+                    best_bid: i.best_bid,
+                    best_ask: i.best_ask,
+                    previous_close: i.previous_close,
+                    day_change: i.last_price.zip(i.previous_close).map(|(last, close)| last - close),
+                    volume_24h: i.volume_24h,
+                    trading_halted: i.trading_halted,
                 })
                 .collect()
         })
@@ -187,369 +339,2291 @@ impl DB {
         Ok(data)
     }
 
+    /// Executions in a stock after tape sequence `since_seq`, oldest first.
+    /// Setup transfers from `add_stock_to_user` aren't on the tape.
     #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
-    pub async fn get_wallet_balance(&self, user_id: i64) -> Result<i64, AppError> {
-        let data = sqlx::query!(
-            r#"
-            WITH TotalDeposits AS (
-                SELECT COALESCE(SUM(d.amount), 0) AS deposits_total
-                FROM deposits d
-                WHERE d.user_id = $1
-            ),
-            TotalTrades AS (
-                SELECT COALESCE(SUM(CASE
-                    WHEN os.user_id = $2 THEN t.amount * os.limit_price
-                    WHEN ob.user_id = $3 THEN -t.amount * os.limit_price
-                    ELSE 0 END
-                ), 0) AS trades_total
-                FROM trades t
-                LEFT JOIN orders os ON os.order_id = t.sell_order
-                LEFT JOIN orders ob ON ob.order_id = t.buy_order
-                WHERE (os.user_id = $4 OR ob.user_id = $5)
-            )
-            SELECT (deposits_total + trades_total) AS "balance!" FROM TotalDeposits, TotalTrades;
-           "#,
-            user_id,
-            user_id,
-            user_id,
-            user_id,
-            user_id
-        )
-        .fetch_one(&self.pool)
-        .await
-        .map(|i| i.balance)
-        .map_err(|e| {
-            error!("{}", &e);
-            AppError::DatabaseError
-        })?;
-
-        Ok(data.to_i64().expect("to turn into i64"))
-    }
-
-    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
-    pub async fn get_wallet_transactions(
+    pub async fn get_market_trades(
         &self,
-        user_id: i64,
-    ) -> Result<Vec<WalletTransaction>, AppError> {
-        let data = sqlx::query_as!(
-            DBWalletTransaction,
+        stock_id: i64,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<MarketTrade>, AppError> {
+        let data = sqlx::query!(
             r#"
-            SELECT t.trade_id AS wallet_tx_id, (t.amount * os.limit_price) AS "amount!", os.user_id AS seller_id, t.created_at AS time_stamp, CASE WHEN ob.user_id = $1 THEN ob.order_id ELSE os.order_id END AS "stock_tx_id!"
-            FROM trades t
-            LEFT JOIN orders os ON os.order_id = t.sell_order
-            LEFT JOIN orders ob ON ob.order_id = t.buy_order
-            WHERE (os.user_id = $2 OR ob.user_id = $3) AND os.created_at != '0001-01-01 00:00:00' AND ob.created_at != '0001-01-01 00:00:00'
-            ORDER BY t.created_at
+            SELECT seq AS "seq!", price, amount, aggressor_is_buy, created_at
+            FROM trades
+            WHERE stock_id = $1 AND seq > $2
+            ORDER BY seq
+            LIMIT $3
            "#,
-            user_id,
-            user_id,
-            user_id
+            stock_id,
+            since_seq,
+            limit
         )
         .fetch_all(&self.pool)
         .await
-        .map(|p| {
-            p.iter()
-                .map(|i| WalletTransaction {
-                        wallet_tx_id: i.wallet_tx_id.to_string(),
-                        stock_tx_id: i.stock_tx_id.to_string(),
-                        is_debit: i.seller_id.ne(&user_id),
-                        amount: i.amount,
-                        time_stamp: i.time_stamp.and_utc(),
-                    })
-                .collect()
-        })
         .map_err(|e| {
-            error!(user_id, "{}", &e);
+            error!(stock_id, since_seq, "{}", &e);
             AppError::DatabaseError
-        })?;
+        })?
+        .into_iter()
+        .map(|t| MarketTrade {
+            seq: t.seq,
+            price: t.price,
+            quantity: t.amount,
+            aggressor: t
+                .aggressor_is_buy
+                .map(|is_buy| if is_buy { Side::Buy } else { Side::Sell }),
+            time_stamp: t.created_at.and_utc(),
+        })
+        .collect();
 
         Ok(data)
     }
 
-    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
-    pub async fn get_stock_portfolio(&self, user_id: i64) -> Result<Vec<StockPortfolio>, AppError> {
-        let data = sqlx::query_as!(
-            DBStockPortfolio,
+    /// Fold the trades with tape sequence `first_seq..=last_seq` into the
+    /// candles of every interval. Trades of a stock are written in sequence
+    /// order, so the latest one always closes its bar.
+    async fn roll_up_candles<'e>(
+        executor: impl PgExecutor<'e>,
+        stock_id: i64,
+        first_seq: i64,
+        last_seq: i64,
+    ) -> Result<(), AppError> {
+        let _ = sqlx::query!(
             r#"
-            SELECT s.stock_id, s.stock_name,
-                SUM(CASE
-                    WHEN o.order_status = $1 AND t.buy_order = o.order_id THEN t.amount -- All buy orders that haven't failed are complete
-                    WHEN o.limit_price IS NOT NULL THEN CASE
-                        WHEN o.order_status IN ($2, $3, $4) THEN -o.amount
-                        WHEN t.sell_order = o.order_id THEN -t.amount
-                        ELSE 0 END
-                    ELSE 0 END
-                ) AS "quantity_owned!"
-            FROM stocks s
-            LEFT JOIN orders o ON s.stock_id = o.stock_id
-            LEFT JOIN trades t ON o.order_id = t.buy_order OR o.order_id = t.sell_order
-            WHERE o.user_id =$5
-            GROUP BY s.stock_id, s.stock_name;
-           "#,
-            OrderStatus::Completed as i64,
-            OrderStatus::Completed as i64,
-            OrderStatus::InProgress as i64,
-            OrderStatus::PartiallyComplete as i64,
-            user_id
+            INSERT INTO candles (stock_id, interval_secs, bucket, open, high, low, close, volume)
+            SELECT t.stock_id, i.secs, date_bin(make_interval(secs => i.secs), t.created_at, TIMESTAMP '2000-01-01') AS bucket,
+                (array_agg(t.price ORDER BY t.seq))[1], MAX(t.price), MIN(t.price), (array_agg(t.price ORDER BY t.seq DESC))[1], SUM(t.amount)
+            FROM trades t CROSS JOIN unnest($4::bigint[]) AS i(secs)
+            WHERE t.stock_id = $1 AND t.seq BETWEEN $2 AND $3
+            GROUP BY t.stock_id, i.secs, bucket
+            ON CONFLICT (stock_id, interval_secs, bucket) DO UPDATE
+            SET high = GREATEST(candles.high, EXCLUDED.high), low = LEAST(candles.low, EXCLUDED.low), close = EXCLUDED.close, volume = candles.volume + EXCLUDED.volume
+            "#,
+            stock_id,
+            first_seq,
+            last_seq,
+            &CandleInterval::ALL.map(|i| i as i64)[..]
         )
-        .fetch_all(&self.pool)
+        .execute(executor)
         .await
-        .map(|p| {
-            p.iter()
-                .map(|i| StockPortfolio {
-                    stock_id: i.stock_id.to_string(),
-                    stock_name: i.stock_name.clone(),
-                    quantity_owned: i.quantity_owned.to_i64().expect("To have less"),
-                })
-                .filter(|i| i.quantity_owned > 0)
-                .collect()
-        })
         .map_err(|e| {
-            error!(user_id, "{}", &e);
+            error!(stock_id, first_seq, last_seq, "{}", &e);
             AppError::DatabaseError
         })?;
 
-        Ok(data)
+        Ok(())
     }
 
-    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
-    pub async fn get_stock_transactions(
-        &self,
-        user_id: i64,
-    ) -> Result<Vec<StockTransaction>, AppError> {
-        let data = sqlx::query_as!(
-            DBStockTransaction,
+    /// Post an approved withdrawal to the cash ledger, moving it out of the
+    /// user's account
+    async fn post_withdrawal<'e>(
+        executor: impl PgExecutor<'e>,
+        withdrawal_id: i64,
+    ) -> Result<(), AppError> {
+        let _ = sqlx::query!(
             r#"
-            SELECT o.order_id AS "stock_tx_id!", -1 AS "parent_stock_tx_id!", o.stock_id AS "stock_id!", o.order_status AS "order_status!", o.limit_price AS stock_price, os.limit_price AS limit_price, o.amount AS "quantity!", o.created_at AS time_stamp, CASE WHEN o.amount = t.amount THEN t.trade_id ELSE -1 END AS "wallet_tx_id!"
-            FROM orders o
-            LEFT JOIN trades t ON t.buy_order = o.order_id
-            LEFT JOIN orders os ON os.order_id = t.sell_order
-            WHERE o.user_id = $1 AND o.created_at != '0001-01-01 00:00:00'
-
-            UNION ALL
-
-            SELECT t.trade_id AS "wallet_tx_id!", os.order_id AS "parent_stock_tx_id!", os.stock_id, $2 AS "order_status!", os.limit_price AS stock_price, 0 AS limit_price, t.amount AS "quantity!", t.created_at AS time_stamp, CASE WHEN ob.user_id = $3 THEN ob.order_id ELSE os.order_id END AS stock_tx_id
-            FROM trades t
-            JOIN orders ob ON ob.order_id = t.buy_order
-            JOIN orders os ON os.order_id = t.sell_order
-            WHERE (os.user_id = $4 OR ob.user_id = $5) AND t.created_at != '0001-01-01 00:00:00' AND (t.amount != ob.amount OR ob.user_id != $6)
-
-            ORDER BY time_stamp
-           "#,
-            user_id,
-            //
-            OrderStatus::Completed as i64,
-            user_id,
-            //
-            user_id, user_id, user_id
-        ).
-        fetch_all(&self.pool)
+            WITH w AS (
+                SELECT w.withdrawal_id, w.amount, a.account_id
+                FROM withdrawals w
+                JOIN ledger_accounts a ON a.user_id = w.user_id
+                WHERE w.withdrawal_id = $1
+            ),
+            e AS (
+                INSERT INTO journal_entries (entry_type, withdrawal_id)
+                SELECT $2, withdrawal_id FROM w
+                RETURNING entry_id
+            ),
+            p AS (
+                INSERT INTO postings (entry_id, account_id, amount)
+                SELECT e.entry_id, x.account_id, x.amount
+                FROM e, w
+                CROSS JOIN LATERAL (VALUES
+                    (w.account_id, -w.amount),
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id IS NULL AND a.account_type = $3), w.amount)
+                ) AS x(account_id, amount)
+                RETURNING account_id, amount
+            ),
+            locked AS (
+                SELECT a.account_id FROM ledger_accounts a
+                WHERE a.account_id IN (SELECT account_id FROM p) AND a.user_id IS NOT NULL
+                ORDER BY a.account_id
+                FOR UPDATE
+            )
+            UPDATE ledger_accounts a SET balance = a.balance + p.total
+            FROM (SELECT account_id, SUM(amount) AS total FROM p GROUP BY account_id) p
+            WHERE a.account_id = p.account_id AND a.account_id IN (SELECT account_id FROM locked)
+            "#,
+            withdrawal_id,
+            EntryType::Withdrawal as i64,
+            AccountType::Funding as i64
+        )
+        .execute(executor)
         .await
-        .map(|p| {
-            p.iter()
-                .map(|i| StockTransaction{
-                        stock_tx_id: i.stock_tx_id.to_string(),
-                        parent_stock_tx_id: if i.parent_stock_tx_id>0 {Some(i.parent_stock_tx_id.to_string())} else {None},
-                        stock_id: i.stock_id.to_string(),
-                        wallet_tx_id: if i.wallet_tx_id > 0 {Some(i.wallet_tx_id.to_string())} else {None},
-                        order_status: i.order_status,
-                        is_buy: i.stock_price.is_none(),
-                        order_type: if i.stock_price.is_some() {OrderType::Limit} else {OrderType::Market},
-                        stock_price: i.stock_price.unwrap_or_else(|| i.limit_price.expect("either stock_price or limit_price to exist")),
-                        quantity: i.quantity,
-                        time_stamp: i.time_stamp.expect("timestamp to exist").and_utc(),
-                    } )
-                .collect()
-        })
         .map_err(|e| {
-            error!(user_id, "{}", &e);
+            error!(withdrawal_id, "{}", &e);
             AppError::DatabaseError
         })?;
 
-        Ok(data)
+        Ok(())
     }
 
-    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
-    pub async fn create_sell_order(
-        &self,
-        user_id: i64,
+    /// Post the trades with tape sequence `first_seq..=last_seq` to the cash
+    /// ledger, moving the notional of each from the buyer to the seller.
+    /// Accounts are locked in order so concurrent postings can't deadlock.
+    /// Only users' accounts keep a balance to update, the exchange's are
+    /// summed from their postings.
+    async fn post_trades<'e>(
+        executor: impl PgExecutor<'e>,
         stock_id: i64,
-        quantity: i64,
-        price: i64,
+        first_seq: i64,
+        last_seq: i64,
     ) -> Result<(), AppError> {
-        let _ = sqlx::query!(r#"
-            INSERT INTO orders (user_id, stock_id, amount, limit_price, order_status) VALUES ($1, $2, $3, $4, $5)"#,
-            user_id, stock_id, quantity, price, OrderStatus::InProgress as i64
+        let _ = sqlx::query!(
+            r#"
+            WITH t AS (
+                SELECT t.trade_id, t.amount * t.price AS notional, ab.account_id AS buyer, asl.account_id AS seller
+                FROM trades t
+                JOIN orders ob ON ob.order_id = t.buy_order
+                JOIN orders os ON os.order_id = t.sell_order
+                JOIN ledger_accounts ab ON ab.user_id = ob.user_id
+                JOIN ledger_accounts asl ON asl.user_id = os.user_id
+                WHERE t.stock_id = $1 AND t.seq BETWEEN $2 AND $3
+            ),
+            e AS (
+                INSERT INTO journal_entries (entry_type, trade_id)
+                SELECT $4, trade_id FROM t
+                RETURNING entry_id, trade_id
+            ),
+            p AS (
+                INSERT INTO postings (entry_id, account_id, amount)
+                SELECT e.entry_id, x.account_id, x.amount
+                FROM e
+                JOIN t ON t.trade_id = e.trade_id
+                CROSS JOIN LATERAL (VALUES (t.buyer, -t.notional), (t.seller, t.notional)) AS x(account_id, amount)
+                RETURNING account_id, amount
+            ),
+            locked AS (
+                SELECT a.account_id FROM ledger_accounts a
+                WHERE a.account_id IN (SELECT account_id FROM p) AND a.user_id IS NOT NULL
+                ORDER BY a.account_id
+                FOR UPDATE
+            )
+            UPDATE ledger_accounts a SET balance = a.balance + p.total
+            FROM (SELECT account_id, SUM(amount) AS total FROM p GROUP BY account_id) p
+            WHERE a.account_id = p.account_id AND a.account_id IN (SELECT account_id FROM locked)
+            "#,
+            stock_id,
+            first_seq,
+            last_seq,
+            EntryType::Trade as i64
         )
-        .execute(&self.pool)
+        .execute(executor)
         .await
         .map_err(|e| {
-            error!(user_id, stock_id, quantity, "{}", &e);
+            error!(stock_id, first_seq, last_seq, "{}", &e);
             AppError::DatabaseError
         })?;
 
         Ok(())
     }
 
-    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
-    pub async fn create_buy_order(
-        &self,
-        user_id: i64,
+    /// Charge the fees on the trades with tape sequence `first_seq..=last_seq`
+    /// to both sides, each at the rate of their tier for providing or taking
+    /// liquidity, and post them to the fee revenue account. Auction prints
+    /// have no taker so both sides pay the maker rate. Nothing is booked for
+    /// a zero fee.
+    async fn book_fees<'e>(
+        executor: impl PgExecutor<'e>,
         stock_id: i64,
-        quantity: i64,
+        first_seq: i64,
+        last_seq: i64,
     ) -> Result<(), AppError> {
-        let mut tx = self.pool.begin().await.unwrap();
-        /*
-        let num = sqlx::query!(
+        let _ = sqlx::query!(
             r#"
-            WITH sold AS (
-                SELECT COALESCE(SUM(o.amount),0) AS amount
+            WITH f AS (
+            INSERT INTO fees (trade_id, user_id, order_id, is_maker, amount)
+            SELECT f.trade_id, f.user_id, f.order_id, f.is_maker, f.amount
+            FROM (
+                SELECT t.trade_id, o.user_id, o.order_id, m.is_maker,
+                    GREATEST(t.amount * t.price * CASE WHEN m.is_maker THEN fs.maker_bps ELSE fs.taker_bps END / 10000, COALESCE(fs.min_fee, 0)) AS amount
                 FROM trades t
-                JOIN orders o ON t.sell_order = o.order_id
-                WHERE o.user_id != $1 AND o.stock_id = $2 AND o.order_status IN ($3,$4)
+                CROSS JOIN LATERAL (VALUES (t.buy_order, t.aggressor_is_buy IS NOT TRUE), (t.sell_order, t.aggressor_is_buy IS NOT FALSE)) AS m(order_id, is_maker)
+                JOIN orders o ON o.order_id = m.order_id
+                JOIN users u ON u.user_id = o.user_id
+                JOIN fee_schedules fs ON fs.tier = u.fee_tier
+                WHERE t.stock_id = $1 AND t.seq BETWEEN $2 AND $3
+            ) f
+            WHERE f.amount > 0
+            RETURNING fee_id, user_id, amount
             ),
-
-            offered AS (
-                SELECT COALESCE(SUM(o.amount),0) AS amount
-                FROM orders o
-                WHERE o.limit_price IS NOT NULL AND o.user_id != $5 AND o.stock_id = $6 AND o.order_status IN ($7, $8)
+            e AS (
+                INSERT INTO journal_entries (entry_type, fee_id)
+                SELECT $4, fee_id FROM f
+                RETURNING entry_id, fee_id
+            ),
+            p AS (
+                INSERT INTO postings (entry_id, account_id, amount)
+                SELECT e.entry_id, x.account_id, x.amount
+                FROM e
+                JOIN f ON f.fee_id = e.fee_id
+                CROSS JOIN LATERAL (VALUES
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id = f.user_id), -f.amount),
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id IS NULL AND a.account_type = $5), f.amount)
+                ) AS x(account_id, amount)
+                RETURNING account_id, amount
+            ),
+            locked AS (
+                SELECT a.account_id FROM ledger_accounts a
+                WHERE a.account_id IN (SELECT account_id FROM p) AND a.user_id IS NOT NULL
+                ORDER BY a.account_id
+                FOR UPDATE
             )
-
-            SELECT (offered.amount - sold.amount) as "amount!" FROM sold, offered;
+            UPDATE ledger_accounts a SET balance = a.balance + p.total
+            FROM (SELECT account_id, SUM(amount) AS total FROM p GROUP BY account_id) p
+            WHERE a.account_id = p.account_id AND a.account_id IN (SELECT account_id FROM locked)
             "#,
-            user_id,
             stock_id,
-            OrderStatus::InProgress as i64,
-            OrderStatus::PartiallyComplete as i64,
-            //
-            user_id,
-            stock_id,
-            OrderStatus::InProgress as i64,
-            OrderStatus::PartiallyComplete as i64,
+            first_seq,
+            last_seq,
+            EntryType::Fee as i64,
+            AccountType::FeeRevenue as i64
         )
-        .fetch_one(&mut *tx)
+        .execute(executor)
         .await
         .map_err(|e| {
-            error!(user_id, stock_id, quantity, "{}", &e);
+            error!(stock_id, first_seq, last_seq, "{}", &e);
             AppError::DatabaseError
-        })?
-        .amount.to_i64().expect("to convert to i64");
-        // Not enough sell orders to fulfill this buy order
-        if num < quantity {
-            error!("NOT ENOUGH STOCKIES");
-            return Ok(());
-        }
-        */
+        })?;
 
-        let buy_order = sqlx::query!(
-            "INSERT INTO orders (user_id, stock_id, amount, order_status) VALUES ($1, $2, $3, $4) RETURNING order_id",
-            user_id,
-            stock_id,
-            quantity,
-            OrderStatus::Completed as i64,
-        )
-        .fetch_one(&mut *tx)
-        .await
-        .map_err(|e| {
-            error!(user_id, stock_id, quantity, "{}", &e);
-            AppError::DatabaseError
-        })?.order_id;
+        Ok(())
+    }
 
-        let cheapest_sell_order = sqlx::query!(
+    /// Fee schedule of the tier a user is on
+    async fn fee_schedule<'e>(
+        executor: impl PgExecutor<'e>,
+        user_id: i64,
+    ) -> Result<FeeSchedule, AppError> {
+        let schedule = sqlx::query_as!(
+            FeeSchedule,
             r#"
-            SELECT order_id, amount, limit_price, user_id
-            FROM orders
-            WHERE stock_id = $1 AND order_status IN ($2, $3) AND user_id != $4
-            ORDER BY limit_price ASC, created_at ASC
-            LIMIT 1
-    "#,
-            stock_id,
-            OrderStatus::InProgress as i64,
-            OrderStatus::PartiallyComplete as i64,
-            user_id,
+            SELECT fs.tier, fs.maker_bps, fs.taker_bps, fs.min_fee
+            FROM users u
+            JOIN fee_schedules fs ON fs.tier = u.fee_tier
+            WHERE u.user_id = $1
+            "#,
+            user_id
         )
-        .fetch_one(&mut *tx)
+        .fetch_one(executor)
         .await
         .map_err(|e| {
-            error!(user_id, stock_id, quantity, "{}", &e);
+            error!(user_id, "{}", &e);
             AppError::DatabaseError
-        })?
-        .order_id;
+        })?;
+
+        Ok(schedule)
+    }
 
+    /// Create or replace the fee schedule of a tier
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
+    pub async fn set_fee_schedule(&self, schedule: &FeeSchedule) -> Result<(), AppError> {
         let _ = sqlx::query!(
             r#"
-            INSERT INTO trades (sell_order, buy_order, amount) VALUES ($1, $2, $3)
-    "#,
-            cheapest_sell_order,
-            buy_order,
-            quantity
+            INSERT INTO fee_schedules (tier, maker_bps, taker_bps, min_fee) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (tier) DO UPDATE SET maker_bps = EXCLUDED.maker_bps, taker_bps = EXCLUDED.taker_bps, min_fee = EXCLUDED.min_fee
+            "#,
+            schedule.tier,
+            schedule.maker_bps,
+            schedule.taker_bps,
+            schedule.min_fee
         )
-        .execute(&mut *tx)
+        .execute(&self.pool)
         .await
         .map_err(|e| {
-            error!(user_id, stock_id, quantity, "{}", &e);
+            error!(schedule.tier, "{}", &e);
             AppError::DatabaseError
         })?;
 
+        Ok(())
+    }
+
+    /// Put a user on the fee schedule of `tier`, which has to exist
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn set_fee_tier(&self, user_name: &str, tier: &str) -> Result<(), AppError> {
         let _ = sqlx::query!(
-            r#"
-            UPDATE orders
-            SET order_status = CASE WHEN amount = $1 THEN $2::bigint ELSE $3 END
-            WHERE order_id = $4
-    "#,
-            quantity,
-            OrderStatus::Completed as i64,
-            OrderStatus::PartiallyComplete as i64,
-            cheapest_sell_order
+            "UPDATE users u SET fee_tier = fs.tier FROM fee_schedules fs WHERE fs.tier = $1 AND u.user_name = $2 RETURNING u.user_id",
+            tier,
+            user_name
         )
-        .execute(&mut *tx)
+        .fetch_optional(&self.pool)
         .await
         .map_err(|e| {
-            error!(user_id, stock_id, quantity, "{}", &e);
+            error!(user_name, tier, "{}", &e);
             AppError::DatabaseError
-        })?;
+        })?
+        .ok_or(AppError::BadRequest)?;
 
-        let _ = tx.commit().await;
         Ok(())
     }
 
-    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
-    pub async fn cancel_sell_order(&self, user_id: i64, stock_tx_id: i64) -> Result<(), AppError> {
-        // TODO: The TA provided tests fail when the user_id is verified
-        //       This seems like a massive security issue.....buuuuuuut
-        let _ = sqlx::query!(
+    /// Bars of a stock between `from` (inclusive) and `to` (exclusive),
+    /// oldest first
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_candles(
+        &self,
+        stock_id: i64,
+        interval: CandleInterval,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<Candle>, AppError> {
+        let data = sqlx::query!(
             r#"
-            UPDATE orders SET order_status = $1 WHERE order_id = $2 AND limit_price IS NOT NULL AND order_status > 0 RETURNING order_id
-            "#,
-            OrderStatus::Cancelled as i64,
-            stock_tx_id,
+            SELECT bucket, open, high, low, close, volume
+            FROM candles
+            WHERE stock_id = $1 AND interval_secs = $2 AND ($3::timestamp IS NULL OR bucket >= $3) AND ($4::timestamp IS NULL OR bucket < $4)
+            ORDER BY bucket
+            LIMIT $5
+           "#,
+            stock_id,
+            interval as i64,
+            from.map(|at| at.naive_utc()),
+            to.map(|at| at.naive_utc()),
+            limit
         )
-        .fetch_optional(&self.pool)
+        .fetch_all(&self.pool)
         .await
         .map_err(|e| {
-            error!(user_id, stock_tx_id, "{}", &e);
+            error!(stock_id, "{}", &e);
             AppError::DatabaseError
         })?
-        .map(|i| i.order_id)
-        .ok_or(AppError::StockTransactionNotFound)?;
+        .into_iter()
+        .map(|c| Candle {
+            time_stamp: c.bucket.and_utc(),
+            open: c.open,
+            high: c.high,
+            low: c.low,
+            close: c.close,
+            volume: c.volume,
+        })
+        .collect();
 
-        Ok(())
+        Ok(data)
     }
-}
+
+    /// Open limit orders of a stock aggregated per price, keeping the best
+    /// `depth` levels on each side
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_order_book(
+        &self,
+        stock_id: i64,
+        depth: i64,
+    ) -> Result<OrderBookDepth, AppError> {
+        let _ = sqlx::query!("SELECT stock_id FROM stocks WHERE stock_id = $1", stock_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| {
+                error!(stock_id, "{}", &e);
+                AppError::DatabaseError
+            })?
+            .ok_or(AppError::StockNotFound)?;
+
+        let levels = sqlx::query!(
+            r#"
+            WITH Filled AS (
+                SELECT o.is_buy, o.limit_price, o.amount, o.display_quantity, (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.buy_order = o.order_id OR t.sell_order = o.order_id) AS filled
+                FROM orders o
+                WHERE o.stock_id = $1 AND o.order_type = $2 AND o.order_status IN ($3, $4)
+            ),
+            -- Icebergs only show their current slice, see `RestingOrder::new`
+            Remaining AS (
+                SELECT is_buy, limit_price, LEAST(amount - filled, COALESCE(display_quantity - filled % display_quantity, amount - filled)) AS remaining
+                FROM Filled
+            ),
+            Levels AS (
+                SELECT is_buy, limit_price, SUM(remaining) AS quantity, COUNT(*) AS order_count,
+                    ROW_NUMBER() OVER (PARTITION BY is_buy ORDER BY CASE WHEN is_buy THEN -limit_price ELSE limit_price END) AS level
+                FROM Remaining
+                GROUP BY is_buy, limit_price
+            )
+            SELECT is_buy AS "is_buy!", limit_price AS "price!", quantity::bigint AS "quantity!", order_count AS "order_count!"
+            FROM Levels
+            WHERE level <= $5
+            ORDER BY level
+           "#,
+            stock_id,
+            OrderType::Limit as i64,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64,
+            depth
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let mut book = OrderBookDepth {
+            stock_id: stock_id.to_string(),
+            bids: vec![],
+            asks: vec![],
+        };
+        for l in levels {
+            let side = if l.is_buy {
+                &mut book.bids
+            } else {
+                &mut book.asks
+            };
+            side.push(PriceLevel {
+                price: l.price,
+                quantity: l.quantity,
+                order_count: l.order_count,
+            });
+        }
+
+        Ok(book)
+    }
+
+    /// Correct the balance of a user, crediting it for a positive amount.
+    /// A debit can't take more than is available
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
+    pub async fn adjust_wallet(
+        &self,
+        user_name: &str,
+        amount: i64,
+        reason: &str,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(user_name, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        // Same per user serialisation as `create_order`
+        let user_id = sqlx::query_scalar!(
+            "SELECT user_id FROM users WHERE user_name = $1 FOR UPDATE",
+            user_name
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_name, amount, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::BadRequest)?;
+
+        if amount < 0 && Self::wallet_balance(&mut *tx, user_id).await?.available < -amount {
+            return Err(AppError::InsufficientFunds);
+        }
+
+        let _ = sqlx::query!(
+            r#"
+            WITH j AS (
+                INSERT INTO adjustments (user_id, amount, reason) VALUES ($1, $2, $3) RETURNING adjustment_id
+            ),
+            e AS (
+                INSERT INTO journal_entries (entry_type, adjustment_id)
+                SELECT $4, adjustment_id FROM j
+                RETURNING entry_id
+            ),
+            p AS (
+                INSERT INTO postings (entry_id, account_id, amount)
+                SELECT e.entry_id, x.account_id, x.amount
+                FROM e
+                CROSS JOIN LATERAL (VALUES
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id = $1), $2),
+                    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id IS NULL AND a.account_type = $5), -$2)
+                ) AS x(account_id, amount)
+                RETURNING account_id, amount
+            ),
+            locked AS (
+                SELECT a.account_id FROM ledger_accounts a
+                WHERE a.account_id IN (SELECT account_id FROM p) AND a.user_id IS NOT NULL
+                ORDER BY a.account_id
+                FOR UPDATE
+            )
+            UPDATE ledger_accounts a SET balance = a.balance + p.total
+            FROM (SELECT account_id, SUM(amount) AS total FROM p GROUP BY account_id) p
+            WHERE a.account_id = p.account_id AND a.account_id IN (SELECT account_id FROM locked)
+            "#,
+            user_id,
+            amount,
+            reason,
+            EntryType::Adjustment as i64,
+            AccountType::Adjustment as i64
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_name, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        tx.commit().await.map_err(|e| {
+            error!(user_name, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        Ok(())
+    }
+
+    /// Withdraw from the available balance. Amounts above the approval
+    /// threshold are held until an admin approves or rejects them
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
+    pub async fn withdraw_money_from_user(
+        &self,
+        user_id: i64,
+        amount: i64,
+    ) -> Result<Withdrawal, AppError> {
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(user_id, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        // Same per user serialisation as `create_order`
+        let _ = sqlx::query!(
+            "SELECT user_id FROM users WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        if Self::wallet_balance(&mut *tx, user_id).await?.available < amount {
+            return Err(AppError::InsufficientFunds);
+        }
+
+        let status = if amount > self.withdrawal_approval_threshold {
+            WithdrawalStatus::Pending
+        } else {
+            WithdrawalStatus::Approved
+        };
+        let row = sqlx::query!(
+            r#"
+            WITH w AS (
+                INSERT INTO withdrawals (user_id, amount, status, decided_at)
+                VALUES ($1, $2, $3::BIGINT, CASE WHEN $3::BIGINT = $4 THEN NULL ELSE LOCALTIMESTAMP END)
+                RETURNING withdrawal_id, user_id, amount, status, created_at
+            )
+            SELECT w.withdrawal_id, u.user_name, w.amount, w.status, w.created_at
+            FROM w
+            JOIN users u ON u.user_id = w.user_id
+            "#,
+            user_id,
+            amount,
+            status as i64,
+            WithdrawalStatus::Pending as i64
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        if status == WithdrawalStatus::Approved {
+            Self::post_withdrawal(&mut *tx, row.withdrawal_id).await?;
+        }
+
+        tx.commit().await.map_err(|e| {
+            error!(user_id, amount, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        Ok(Withdrawal {
+            withdrawal_id: row.withdrawal_id.to_string(),
+            user_name: row.user_name,
+            amount: row.amount,
+            status: row.status.into(),
+            time_stamp: row.created_at.and_utc(),
+        })
+    }
+
+    /// Approve or reject a pending withdrawal, an approved one leaves the
+    /// user's account. The decider can't be the user who asked for it
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn decide_withdrawal(
+        &self,
+        withdrawal_id: i64,
+        decided_by: i64,
+        status: WithdrawalStatus,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(withdrawal_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let owner = sqlx::query_scalar!(
+            "UPDATE withdrawals SET status = $2, decided_at = LOCALTIMESTAMP WHERE withdrawal_id = $1 AND status = $3 RETURNING user_id",
+            withdrawal_id,
+            status as i64,
+            WithdrawalStatus::Pending as i64
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(withdrawal_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::WithdrawalNotFound)?;
+
+        // Nobody decides on their own withdrawal; dropping the transaction
+        // leaves it pending
+        if owner == decided_by {
+            return Err(AppError::Forbidden);
+        }
+
+        if status == WithdrawalStatus::Approved {
+            Self::post_withdrawal(&mut *tx, withdrawal_id).await?;
+        }
+
+        tx.commit().await.map_err(|e| {
+            error!(withdrawal_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        Ok(())
+    }
+
+    /// Withdrawals of a user, or those of every user waiting for approval
+    /// without one
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_withdrawals(&self, user_id: Option<i64>) -> Result<Vec<Withdrawal>, AppError> {
+        let data = sqlx::query!(
+            r#"
+            SELECT w.withdrawal_id, u.user_name, w.amount, w.status, w.created_at
+            FROM withdrawals w
+            JOIN users u ON u.user_id = w.user_id
+            WHERE CASE WHEN $1::BIGINT IS NULL THEN w.status = $2 ELSE w.user_id = $1 END
+            ORDER BY w.withdrawal_id
+            "#,
+            user_id,
+            WithdrawalStatus::Pending as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        Ok(data
+            .into_iter()
+            .map(|w| Withdrawal {
+                withdrawal_id: w.withdrawal_id.to_string(),
+                user_name: w.user_name,
+                amount: w.amount,
+                status: w.status.into(),
+                time_stamp: w.created_at.and_utc(),
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_wallet_balance(&self, user_id: i64) -> Result<Balance, AppError> {
+        Self::wallet_balance(&self.pool, user_id).await
+    }
+
+    /// Cash balance of a user's ledger account, along with the part of it
+    /// held for their open limit buys and pending withdrawals. A resting buy
+    /// holds its worst-case cost (the unfilled quantity at its limit price
+    /// plus the larger fee on that) and each fill releases its share of that.
+    async fn wallet_balance<'e>(
+        executor: impl PgExecutor<'e>,
+        user_id: i64,
+    ) -> Result<Balance, AppError> {
+        let data = sqlx::query!(
+            r#"
+            WITH OpenBuys AS (
+                SELECT (o.amount - (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.buy_order = o.order_id)) * o.limit_price AS notional
+                FROM orders o
+                WHERE o.user_id = $1 AND o.is_buy AND o.order_type = $2 AND o.order_status IN ($3, $4)
+            ),
+            -- Along with the notional, the most the user's tier could charge for it
+            TotalHeld AS (
+                SELECT COALESCE(SUM(b.notional + GREATEST(b.notional * GREATEST(fs.maker_bps, fs.taker_bps) / 10000, COALESCE(fs.min_fee, 0))), 0) AS held_total
+                FROM OpenBuys b, users u
+                JOIN fee_schedules fs ON fs.tier = u.fee_tier
+                WHERE u.user_id = $1
+            ),
+            TotalWithdrawing AS (
+                SELECT COALESCE(SUM(w.amount), 0) AS withdrawing_total
+                FROM withdrawals w
+                WHERE w.user_id = $1 AND w.status = $5
+            )
+            SELECT COALESCE((SELECT a.balance FROM ledger_accounts a WHERE a.user_id = $1), 0) AS "balance!", (held_total + withdrawing_total) AS "held!" FROM TotalHeld, TotalWithdrawing;
+           "#,
+            user_id,
+            OrderType::Limit as i64,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64,
+            WithdrawalStatus::Pending as i64,
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let balance = data.balance;
+        let held = data.held.to_i64().expect("to turn into i64");
+        Ok(Balance {
+            balance,
+            available: balance - held,
+            held,
+        })
+    }
+
+    /// A page of the movements of a user's cash, oldest first, along with
+    /// the cursor of the page after it
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_wallet_transactions(
+        &self,
+        user_id: i64,
+        filter: &HistoryFilter,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<(Vec<WalletTransaction>, Option<String>), AppError> {
+        let after = after.map(decode_cursor::<1>).transpose()?;
+        let mut data = sqlx::query_as!(
+            DBWalletTransaction,
+            r#"
+            SELECT w.wallet_tx_id AS "wallet_tx_id!", w.kind AS "kind!", w.amount AS "amount!", w.is_debit AS "is_debit!", w.time_stamp AS "time_stamp!", w.stock_tx_id AS "stock_tx_id?"
+            FROM (
+                SELECT COALESCE(e.deposit_id, e.trade_id, e.fee_id, e.withdrawal_id, e.adjustment_id) AS wallet_tx_id, e.entry_type AS kind, ABS(SUM(p.amount))::bigint AS amount, SUM(p.amount) < 0 AS is_debit, e.created_at AS time_stamp, COALESCE(f.order_id, CASE WHEN ob.user_id = $1 THEN ob.order_id ELSE os.order_id END) AS stock_tx_id
+                FROM postings p
+                JOIN ledger_accounts a ON a.account_id = p.account_id
+                JOIN journal_entries e ON e.entry_id = p.entry_id
+                LEFT JOIN trades t ON t.trade_id = e.trade_id
+                LEFT JOIN orders ob ON ob.order_id = t.buy_order
+                LEFT JOIN orders os ON os.order_id = t.sell_order
+                LEFT JOIN fees f ON f.fee_id = e.fee_id
+                WHERE a.user_id = $1
+                    AND ($2::BIGINT IS NULL OR e.entry_type = $2)
+                    AND ($7::TIMESTAMP IS NULL OR e.created_at >= $7)
+                    AND ($8::TIMESTAMP IS NULL OR e.created_at < $8)
+                    AND ($9::TIMESTAMP IS NULL OR (e.created_at, COALESCE(e.deposit_id, e.trade_id, e.fee_id, e.withdrawal_id, e.adjustment_id)) > ($9, $10::BIGINT))
+                -- Both legs of a self-trade are posted to the same account
+                GROUP BY e.entry_id, f.fee_id, ob.order_id, os.order_id
+            ) w
+            LEFT JOIN orders o ON o.order_id = w.stock_tx_id
+            WHERE ($3::BIGINT IS NULL OR o.stock_id = $3)
+                AND ($4::BOOLEAN IS NULL OR o.is_buy = $4)
+                AND ($5::BIGINT IS NULL OR o.order_status = $5)
+                AND ($6::BIGINT IS NULL OR o.order_type = $6)
+            -- Fees are booked right after the trade they're charged on
+            ORDER BY w.time_stamp, w.wallet_tx_id
+            LIMIT $11
+           "#,
+            user_id,
+            filter.kind.map(|k| k as i64),
+            filter.stock_id,
+            filter.side.map(|s| s == Side::Buy),
+            filter.order_status.map(|s| s as i64),
+            filter.order_type.map(|t| t as i64),
+            filter.from.map(|at| at.naive_utc()),
+            filter.to.map(|at| at.naive_utc()),
+            after.map(|(at, _)| at),
+            after.map(|(_, [id])| id),
+            // One more than asked for, to know whether there's a next page
+            limit + 1
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let next_cursor = if data.len() as i64 > limit {
+            data.truncate(limit as usize);
+            data.last()
+                .map(|i| encode_cursor(i.time_stamp, &[i.wallet_tx_id]))
+        } else {
+            None
+        };
+        let data = data
+            .into_iter()
+            .map(|i| WalletTransaction {
+                wallet_tx_id: i.wallet_tx_id.to_string(),
+                kind: i.kind.into(),
+                stock_tx_id: i.stock_tx_id.map(|id| id.to_string()),
+                is_debit: i.is_debit,
+                amount: i.amount,
+                time_stamp: i.time_stamp.and_utc(),
+            })
+            .collect();
+
+        Ok((data, next_cursor))
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_stock_portfolio(
+        &self,
+        user_id: i64,
+        lot_method: LotMethod,
+    ) -> Result<Vec<StockPortfolio>, AppError> {
+        let data = self
+            .portfolio(user_id, lot_method)
+            .await?
+            .into_iter()
+            .filter(|i| i.quantity_owned > 0)
+            .collect();
+
+        Ok(data)
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_portfolio_summary(
+        &self,
+        user_id: i64,
+        lot_method: LotMethod,
+    ) -> Result<PortfolioSummary, AppError> {
+        let positions = self.portfolio(user_id, lot_method).await?;
+
+        Ok(PortfolioSummary {
+            cost_basis: positions.iter().map(|i| i.cost_basis).sum(),
+            market_value: positions.iter().filter_map(|i| i.market_value).sum(),
+            unrealized_pnl: positions.iter().filter_map(|i| i.unrealized_pnl).sum(),
+            realized_pnl: positions.iter().map(|i| i.realized_pnl).sum(),
+        })
+    }
+
+    /// Every stock a user placed orders for with its cost basis and profit,
+    /// replayed from the user's fills. Setup transfers from
+    /// `add_stock_to_user` have no cost and count towards neither profit
+    /// figure
+    async fn portfolio(
+        &self,
+        user_id: i64,
+        lot_method: LotMethod,
+    ) -> Result<Vec<StockPortfolio>, AppError> {
+        let stocks = sqlx::query_as!(
+            DBStockPortfolio,
+            r#"
+            SELECT s.stock_id, s.stock_name,
+                SUM(CASE
+                    WHEN o.is_buy THEN (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.buy_order = o.order_id)
+                    WHEN o.order_type = $1 AND o.order_status IN ($2, $3) THEN -o.amount -- Shares offered for sale are no longer owned
+                    ELSE -(SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.sell_order = o.order_id)
+                    END
+                ) AS "quantity_owned!",
+                (SELECT t.price FROM trades t WHERE t.stock_id = s.stock_id AND t.seq IS NOT NULL ORDER BY t.seq DESC LIMIT 1) AS last_price
+            FROM stocks s
+            JOIN orders o ON s.stock_id = o.stock_id
+            WHERE o.user_id = $4
+            GROUP BY s.stock_id, s.stock_name
+            ORDER BY s.stock_id;
+           "#,
+            OrderType::Limit as i64,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        // Both sides of a self-trade are replayed, the buy first
+        let fills = sqlx::query!(
+            r#"
+            SELECT o.stock_id, o.is_buy, t.amount, t.price, COALESCE(f.amount, 0) AS "fee!", t.seq IS NULL AS "seeded!"
+            FROM trades t
+            JOIN orders o ON o.order_id IN (t.buy_order, t.sell_order)
+            LEFT JOIN fees f ON f.trade_id = t.trade_id AND f.order_id = o.order_id
+            WHERE o.user_id = $1
+            ORDER BY t.created_at, t.trade_id, o.is_buy DESC
+           "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .into_iter()
+        .map(|i| portfolio::Fill {
+            stock_id: i.stock_id,
+            is_buy: i.is_buy,
+            quantity: i.amount,
+            price: i.price,
+            fee: i.fee,
+            seeded: i.seeded,
+        })
+        .collect::<Vec<_>>();
+        let mut positions = portfolio::replay(&fills, lot_method);
+
+        let data = stocks
+            .into_iter()
+            .map(|i| {
+                let position = positions.remove(&i.stock_id).unwrap_or_default();
+                let market_value = i.last_price.map(|price| price * position.quantity);
+                let bought = position.quantity - position.seeded;
+                StockPortfolio {
+                    stock_id: i.stock_id.to_string(),
+                    stock_name: i.stock_name,
+                    quantity_owned: i.quantity_owned.to_i64().expect("To have less"),
+                    average_cost: (bought > 0).then(|| position.cost_basis / bought),
+                    cost_basis: position.cost_basis,
+                    market_value,
+                    unrealized_pnl: i
+                        .last_price
+                        .map(|price| price * bought - position.cost_basis),
+                    realized_pnl: position.realized_pnl,
+                }
+            })
+            .collect();
+
+        Ok(data)
+    }
+
+    /// Shares of a stock a user can still offer for sale: everything bought
+    /// minus everything sold or locked in a resting sell order. Mirrors the
+    /// per-stock quantity in [`DB::get_stock_portfolio`].
+    async fn stock_position<'e>(
+        executor: impl PgExecutor<'e>,
+        user_id: i64,
+        stock_id: i64,
+    ) -> Result<i64, AppError> {
+        let quantity = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(CASE
+                WHEN o.is_buy THEN (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.buy_order = o.order_id)
+                WHEN o.order_type = $1 AND o.order_status IN ($2, $3) THEN -o.amount
+                ELSE -(SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.sell_order = o.order_id)
+                END
+            ), 0) AS "quantity!"
+            FROM orders o
+            WHERE o.user_id = $4 AND o.stock_id = $5
+           "#,
+            OrderType::Limit as i64,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64,
+            user_id,
+            stock_id
+        )
+        .fetch_one(executor)
+        .await
+        .map_err(|e| {
+            error!(user_id, stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .quantity;
+
+        Ok(quantity.to_i64().expect("to turn into i64"))
+    }
+
+    /// A page of a user's orders and their fills, oldest first, along with
+    /// the cursor of the page after it
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_stock_transactions(
+        &self,
+        user_id: i64,
+        filter: &HistoryFilter,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<(Vec<StockTransaction>, Option<String>), AppError> {
+        let after = after.map(decode_cursor::<3>).transpose()?;
+        // Rows sort by time, then parent so an order comes before its fills,
+        // then orders before fills, then id. The cursor and filters are
+        // applied to both halves so each can use its indexes
+        let mut data = sqlx::query_as!(
+            DBStockTransaction,
+            r#"
+            SELECT TRUE AS "is_order!", o.order_id AS "stock_tx_id!", COALESCE(o.parent_order, -1) AS "parent_stock_tx_id!", o.stock_id AS "stock_id!", o.order_status AS "order_status!", o.is_buy AS "is_buy!", o.order_type AS "order_type!", COALESCE(o.limit_price, SUM(t.amount * t.price) / NULLIF(SUM(t.amount), 0), 0)::bigint AS "stock_price!", o.amount AS "quantity!", o.time_in_force AS "time_in_force!", o.expiry_reason, o.self_trade_prevention AS "self_trade_prevention!", o.display_quantity, (SELECT g.group_type FROM order_groups g WHERE g.group_id = o.group_id) AS order_group, o.trigger_price, o.triggered_at, o.created_at AS "time_stamp!", CASE WHEN o.order_type = $2 AND COUNT(t.trade_id) = 1 AND MIN(t.amount) = o.amount THEN MIN(t.trade_id) ELSE -1 END AS "wallet_tx_id!"
+            FROM orders o
+            LEFT JOIN trades t ON t.buy_order = o.order_id OR t.sell_order = o.order_id
+            WHERE o.user_id = $1 AND o.created_at != '0001-01-01 00:00:00'
+                AND ($4::BIGINT IS NULL OR o.stock_id = $4)
+                AND ($5::BOOLEAN IS NULL OR o.is_buy = $5)
+                AND ($6::BIGINT IS NULL OR o.order_status = $6)
+                AND ($7::BIGINT IS NULL OR o.order_type = $7)
+                AND ($8::TIMESTAMP IS NULL OR o.created_at >= $8)
+                AND ($9::TIMESTAMP IS NULL OR o.created_at < $9)
+                AND ($10::TIMESTAMP IS NULL OR (o.created_at, COALESCE(o.parent_order, -1), 0::BIGINT, o.order_id) > ($10, $11::BIGINT, $12::BIGINT, $13::BIGINT))
+            GROUP BY o.order_id
+
+            UNION ALL
+
+            -- Every fill of the user's orders, except a market order filled in one go which is already fully described by its order row
+            SELECT FALSE, t.trade_id, o.order_id, o.stock_id, $3, o.is_buy, o.order_type, t.price, t.amount, o.time_in_force, NULL::bigint, o.self_trade_prevention, o.display_quantity, NULL::bigint, NULL::bigint, NULL::timestamp, t.created_at, t.trade_id
+            FROM trades t
+            JOIN orders o ON o.order_id = t.buy_order OR o.order_id = t.sell_order
+            WHERE o.user_id = $1 AND t.created_at != '0001-01-01 00:00:00' AND NOT (o.order_type = $2 AND t.amount = o.amount)
+                AND ($4::BIGINT IS NULL OR o.stock_id = $4)
+                AND ($5::BOOLEAN IS NULL OR o.is_buy = $5)
+                AND ($6::BIGINT IS NULL OR $6 = $3)
+                AND ($7::BIGINT IS NULL OR o.order_type = $7)
+                AND ($8::TIMESTAMP IS NULL OR t.created_at >= $8)
+                AND ($9::TIMESTAMP IS NULL OR t.created_at < $9)
+                AND ($10::TIMESTAMP IS NULL OR (t.created_at, o.order_id, 1::BIGINT, t.trade_id) > ($10, $11::BIGINT, $12::BIGINT, $13::BIGINT))
+
+            ORDER BY "time_stamp!", "parent_stock_tx_id!", "is_order!" DESC, "stock_tx_id!"
+            LIMIT $14
+           "#,
+            user_id,
+            OrderType::Market as i64,
+            OrderStatus::Completed as i64,
+            filter.stock_id,
+            filter.side.map(|s| s == Side::Buy),
+            filter.order_status.map(|s| s as i64),
+            filter.order_type.map(|t| t as i64),
+            filter.from.map(|at| at.naive_utc()),
+            filter.to.map(|at| at.naive_utc()),
+            after.map(|(at, _)| at),
+            after.map(|(_, [parent, _, _])| parent),
+            after.map(|(_, [_, is_fill, _])| is_fill),
+            after.map(|(_, [_, _, id])| id),
+            // One more than asked for, to know whether there's a next page
+            limit + 1
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let next_cursor = if data.len() as i64 > limit {
+            data.truncate(limit as usize);
+            data.last().map(|i| {
+                encode_cursor(
+                    i.time_stamp,
+                    &[i.parent_stock_tx_id, (!i.is_order).into(), i.stock_tx_id],
+                )
+            })
+        } else {
+            None
+        };
+
+        let order_ids: Vec<i64> = data
+            .iter()
+            .filter(|i| i.is_order)
+            .map(|i| i.stock_tx_id)
+            .collect();
+        let mut amendments: HashMap<i64, Vec<OrderAmendment>> = HashMap::new();
+        for a in sqlx::query!(
+            r#"
+            SELECT a.order_id, a.previous_amount, a.previous_limit_price, a.amount, a.limit_price, a.kept_priority, a.created_at
+            FROM order_amendments a
+            WHERE a.order_id = ANY($1)
+            ORDER BY a.amendment_id
+           "#,
+            &order_ids
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, "{}", &e);
+            AppError::DatabaseError
+        })? {
+            amendments.entry(a.order_id).or_default().push(OrderAmendment {
+                previous_quantity: a.previous_amount,
+                previous_price: a.previous_limit_price,
+                quantity: a.amount,
+                price: a.limit_price,
+                kept_priority: a.kept_priority,
+                time_stamp: a.created_at.and_utc(),
+            });
+        }
+
+        let data = data
+            .into_iter()
+            .map(|i| StockTransaction {
+                stock_tx_id: i.stock_tx_id.to_string(),
+                parent_stock_tx_id: if i.parent_stock_tx_id > 0 {
+                    Some(i.parent_stock_tx_id.to_string())
+                } else {
+                    None
+                },
+                stock_id: i.stock_id.to_string(),
+                wallet_tx_id: if i.wallet_tx_id > 0 {
+                    Some(i.wallet_tx_id.to_string())
+                } else {
+                    None
+                },
+                order_status: i.order_status,
+                is_buy: i.is_buy,
+                order_type: i.order_type,
+                stock_price: i.stock_price,
+                quantity: i.quantity,
+                time_in_force: i.time_in_force,
+                expiry_reason: i.expiry_reason.map(ExpiryReason::from),
+                self_trade_prevention: i.self_trade_prevention,
+                display_quantity: i.display_quantity,
+                order_group: i.order_group.map(OrderGroupType::from),
+                trigger_price: i.trigger_price,
+                triggered_at: i.triggered_at.map(|at| at.and_utc()),
+                amendments: if i.is_order {
+                    amendments.remove(&i.stock_tx_id).unwrap_or_default()
+                } else {
+                    vec![]
+                },
+                time_stamp: i.time_stamp.and_utc(),
+            })
+            .collect();
+
+        Ok((data, next_cursor))
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_open_orders(&self) -> Result<Vec<DbOpenOrder>, AppError> {
+        let data = sqlx::query_as!(
+            DbOpenOrder,
+            r#"
+            SELECT o.order_id, o.user_id, o.stock_id, o.is_buy, o.limit_price AS "limit_price!", COALESCE(SUM(t.amount), 0)::bigint AS "filled!", (o.amount - COALESCE(SUM(t.amount), 0))::bigint AS "remaining!", o.display_quantity, o.self_trade_prevention
+            FROM orders o
+            LEFT JOIN trades t ON t.sell_order = o.order_id OR t.buy_order = o.order_id
+            WHERE o.order_type = $1 AND o.order_status IN ($2, $3)
+            GROUP BY o.order_id
+            ORDER BY o.queued_at, o.order_id
+           "#,
+            OrderType::Limit as i64,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        Ok(data)
+    }
+
+    /// Change the quantity and/or price of one of the user's open limit
+    /// orders and record the amendment, returning the quantity already filled
+    /// and the quantity left to fill.
+    /// Anything but a smaller quantity at the same price resets the order's
+    /// time priority.
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn amend_order(
+        &self,
+        user_id: i64,
+        order_id: i64,
+        quantity: Option<i64>,
+        price: Option<i64>,
+    ) -> Result<(i64, i64), AppError> {
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        // Same per user serialisation as `create_order`
+        let _ = sqlx::query!(
+            "SELECT user_id FROM users WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let order = sqlx::query!(
+            r#"
+            SELECT o.stock_id, o.is_buy, o.amount, o.limit_price AS "limit_price!", (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.buy_order = o.order_id OR t.sell_order = o.order_id)::bigint AS "filled!"
+            FROM orders o
+            WHERE o.order_id = $1 AND o.user_id = $2 AND o.order_type = $3 AND o.order_status IN ($4, $5)
+            FOR UPDATE
+            "#,
+            order_id,
+            user_id,
+            OrderType::Limit as i64,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockTransactionNotFound)?;
+
+        let (amount, limit_price) = (
+            quantity.unwrap_or(order.amount),
+            price.unwrap_or(order.limit_price),
+        );
+        if amount <= order.filled || limit_price <= 0 {
+            return Err(AppError::BadRequest);
+        }
+
+        if order.is_buy {
+            let extra = (amount - order.filled) * limit_price
+                - (order.amount - order.filled) * order.limit_price;
+            if extra > 0 && Self::wallet_balance(&mut *tx, user_id).await?.available < extra {
+                return Err(AppError::InsufficientFunds);
+            }
+        } else if amount > order.amount
+            && Self::stock_position(&mut *tx, user_id, order.stock_id).await?
+                < amount - order.amount
+        {
+            return Err(AppError::InsufficientShares);
+        }
+
+        let kept_priority = limit_price == order.limit_price && amount <= order.amount;
+        let _ = sqlx::query!(
+            r#"
+            UPDATE orders
+            SET amount = $1, limit_price = $2, queued_at = CASE WHEN $3 THEN queued_at ELSE clock_timestamp() END
+            WHERE order_id = $4
+            "#,
+            amount,
+            limit_price,
+            kept_priority,
+            order_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let _ = sqlx::query!(
+            r#"
+            INSERT INTO order_amendments (order_id, previous_amount, previous_limit_price, amount, limit_price, kept_priority) VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            order_id,
+            order.amount,
+            order.limit_price,
+            amount,
+            limit_price,
+            kept_priority
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        tx.commit().await.map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        Ok((order.filled, amount - order.filled))
+    }
+
+    /// Stop orders still waiting for their trigger, oldest first
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_stop_orders(&self) -> Result<Vec<DbStopOrder>, AppError> {
+        let data = sqlx::query_as!(
+            DbStopOrder,
+            r#"
+            SELECT order_id, stock_id, is_buy, trigger_price AS "trigger_price!"
+            FROM orders
+            WHERE order_type IN ($1, $2) AND order_status = $3
+            ORDER BY created_at, order_id
+           "#,
+            OrderType::Stop as i64,
+            OrderType::StopLimit as i64,
+            OrderStatus::InProgress as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        Ok(data)
+    }
+
+    /// Mark a dormant stop order as triggered and return the market or
+    /// limit order it turns into, along with the other legs of its group
+    /// that this cancelled
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn trigger_stop(&self, order_id: i64) -> Result<(NewOrder, Vec<i64>), AppError> {
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let row = sqlx::query!(
+            r#"
+            UPDATE orders SET order_status = $1, triggered_at = CURRENT_TIMESTAMP
+            WHERE order_id = $2 AND order_type IN ($3, $4) AND order_status = $5
+            RETURNING user_id, stock_id, is_buy, order_type, amount, limit_price, time_in_force, expires_at, self_trade_prevention, display_quantity
+            "#,
+            OrderStatus::Completed as i64,
+            order_id,
+            OrderType::Stop as i64,
+            OrderType::StopLimit as i64,
+            OrderStatus::InProgress as i64
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(order_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockTransactionNotFound)?;
+
+        let cancelled = Self::cancel_siblings(&mut *tx, &[order_id]).await?;
+
+        tx.commit().await.map_err(|e| {
+            error!(order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        let order = NewOrder {
+            user_id: row.user_id,
+            stock_id: row.stock_id,
+            is_buy: row.is_buy,
+            order_type: OrderType::from(row.order_type).triggered(),
+            quantity: row.amount,
+            price: row.limit_price,
+            time_in_force: TimeInForce::from(row.time_in_force),
+            expires_at: row.expires_at.map(|at| at.and_utc()),
+            trigger_price: None,
+            parent_order: Some(order_id),
+            self_trade_prevention: SelfTradePrevention::from(row.self_trade_prevention),
+            display_quantity: row.display_quantity,
+            group_id: None,
+        };
+
+        Ok((order, cancelled))
+    }
+
+    /// Cancel the open orders grouped with any of `order_ids` now that those
+    /// have traded or triggered, returning the ids cancelled. A limit leg
+    /// still open after trading instead shrinks its stop legs to what it has
+    /// left.
+    async fn cancel_siblings<'e>(
+        executor: impl PgExecutor<'e>,
+        order_ids: &[i64],
+    ) -> Result<Vec<i64>, AppError> {
+        let cancelled = sqlx::query!(
+            r#"
+            WITH pairs AS (
+                SELECT s.order_id AS sibling, o.order_id,
+                    o.order_type = $6 AND o.order_status = $5 AND s.order_type IN ($7, $8) AS shrinks
+                FROM orders o
+                JOIN orders s ON s.group_id = o.group_id AND s.order_id != o.order_id AND s.order_status IN ($4, $5)
+                WHERE o.order_id = ANY($3)
+            ),
+            shrunk AS (
+                UPDATE orders s
+                SET amount = o.amount - (SELECT COALESCE(SUM(t.amount), 0) FROM trades t WHERE t.sell_order = o.order_id OR t.buy_order = o.order_id)
+                FROM pairs p
+                JOIN orders o ON o.order_id = p.order_id
+                WHERE s.order_id = p.sibling AND p.shrinks
+            )
+            UPDATE orders s SET order_status = $1, expiry_reason = $2
+            FROM pairs p
+            WHERE s.order_id = p.sibling AND NOT p.shrinks
+            RETURNING s.order_id
+            "#,
+            OrderStatus::Cancelled as i64,
+            ExpiryReason::OtherLegExecuted as i64,
+            order_ids,
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64,
+            OrderType::Limit as i64,
+            OrderType::Stop as i64,
+            OrderType::StopLimit as i64
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(|e| {
+            error!(?order_ids, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .into_iter()
+        .map(|r| r.order_id)
+        .collect();
+
+        Ok(cancelled)
+    }
+
+    /// Brackets whose entry is among `order_ids` and has completely filled
+    async fn filled_brackets<'e>(
+        executor: impl PgExecutor<'e>,
+        order_ids: &[i64],
+    ) -> Result<Vec<i64>, AppError> {
+        let group_ids = sqlx::query!(
+            r#"
+            SELECT o.group_id AS "group_id!"
+            FROM orders o
+            JOIN order_groups g ON g.group_id = o.group_id
+            WHERE o.order_id = ANY($1) AND g.group_type = $2 AND o.parent_order IS NULL AND o.order_status = $3
+            ORDER BY o.group_id
+           "#,
+            order_ids,
+            OrderGroupType::Bracket as i64,
+            OrderStatus::Completed as i64
+        )
+        .fetch_all(executor)
+        .await
+        .map_err(|e| {
+            error!(?order_ids, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .into_iter()
+        .map(|r| r.group_id)
+        .collect();
+
+        Ok(group_ids)
+    }
+
+    /// Brackets whose entry has filled but whose exits haven't been placed
+    /// yet, along with their stock
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_unplaced_brackets(&self) -> Result<Vec<(i64, i64)>, AppError> {
+        let data = sqlx::query!(
+            r#"
+            SELECT g.group_id, o.stock_id
+            FROM order_groups g
+            JOIN orders o ON o.group_id = g.group_id AND o.parent_order IS NULL
+            WHERE g.group_type = $1 AND o.order_status = $2 AND NOT EXISTS (SELECT 1 FROM orders e WHERE e.parent_order = o.order_id)
+            ORDER BY g.group_id
+           "#,
+            OrderGroupType::Bracket as i64,
+            OrderStatus::Completed as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        Ok(data.into_iter().map(|r| (r.group_id, r.stock_id)).collect())
+    }
+
+    /// The stop-loss and take-profit exits of a bracket whose entry has
+    /// filled, or `None` if they have already been placed
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_bracket_exits(
+        &self,
+        group_id: i64,
+    ) -> Result<Option<(NewOrder, NewOrder)>, AppError> {
+        let Some(entry) = sqlx::query!(
+            r#"
+            SELECT o.order_id, o.user_id, o.stock_id, o.is_buy, o.amount, o.self_trade_prevention, g.take_profit_price AS "take_profit_price!", g.stop_loss_price AS "stop_loss_price!"
+            FROM order_groups g
+            JOIN orders o ON o.group_id = g.group_id AND o.parent_order IS NULL
+            WHERE g.group_id = $1 AND g.group_type = $2 AND o.order_status = $3 AND NOT EXISTS (SELECT 1 FROM orders e WHERE e.parent_order = o.order_id)
+           "#,
+            group_id,
+            OrderGroupType::Bracket as i64,
+            OrderStatus::Completed as i64
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(group_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        else {
+            return Ok(None);
+        };
+
+        let exit = NewOrder {
+            user_id: entry.user_id,
+            stock_id: entry.stock_id,
+            is_buy: !entry.is_buy,
+            order_type: OrderType::Stop,
+            quantity: entry.amount,
+            price: None,
+            time_in_force: TimeInForce::ImmediateOrCancel,
+            expires_at: None,
+            trigger_price: Some(entry.stop_loss_price),
+            parent_order: Some(entry.order_id),
+            self_trade_prevention: SelfTradePrevention::from(entry.self_trade_prevention),
+            display_quantity: None,
+            group_id: Some(group_id),
+        };
+        Ok(Some((
+            exit.clone(),
+            NewOrder {
+                order_type: OrderType::Limit,
+                price: Some(entry.take_profit_price),
+                time_in_force: TimeInForce::GoodTilCancelled,
+                trigger_price: None,
+                ..exit
+            },
+        )))
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn fail_order(&self, order_id: i64) -> Result<(), AppError> {
+        let _ = sqlx::query!(
+            "UPDATE orders SET order_status = $1 WHERE order_id = $2",
+            OrderStatus::Failed as i64,
+            order_id
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(order_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        Ok(())
+    }
+
+    /// Stock of an order. Given a user, other users' orders aren't found
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_order_stock(
+        &self,
+        user_id: Option<i64>,
+        order_id: i64,
+    ) -> Result<i64, AppError> {
+        let stock_id = sqlx::query!(
+            "SELECT stock_id FROM orders WHERE order_id = $1 AND ($2::BIGINT IS NULL OR user_id = $2)",
+            order_id,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, order_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockTransactionNotFound)?
+        .stock_id;
+
+        Ok(stock_id)
+    }
+
+    /// Persist an incoming order along with the fills the matching engine
+    /// produced for it, returning the new order's id. Whatever the fills
+    /// don't cover rests on the book for GTC and GTD limit orders. Otherwise
+    /// it is dropped along with an expiry reason: a plain market order ends
+    /// up `Completed`, `PartiallyComplete` or `Cancelled` depending on how
+    /// much of it filled, while IOC limit and FOK orders end up `Expired`.
+    /// Stop orders come without fills and stay `InProgress` until triggered;
+    /// their funds and shares are checked here but only held once triggered.
+    /// Orders cancelled by self-trade prevention, incoming or resting, are
+    /// `Cancelled` with that as their expiry reason. Any order that trades
+    /// cancels the other open legs of its group, and an entry that fills
+    /// completely makes its bracket's exits due. A new group and the stop
+    /// leg covering what's left of the order go in the same transaction.
+    #[tracing::instrument(skip(self, matched), fields(service.name = "db", db.operation.name = "INSERT"))]
+    pub async fn create_order(
+        &self,
+        order: &NewOrder,
+        matched: &Match,
+        group: Option<NewGroup>,
+        stop: Option<&NewOrder>,
+    ) -> Result<CreatedOrder, AppError> {
+        let NewOrder {
+            user_id,
+            stock_id,
+            is_buy,
+            order_type,
+            quantity,
+            price,
+            time_in_force,
+            expires_at,
+            trigger_price,
+            parent_order,
+            self_trade_prevention,
+            display_quantity,
+            group_id,
+        } = *order;
+        let fills = &matched.fills;
+        // Whatever doesn't fill stays open, either resting on the book or,
+        // for a stop, waiting for its trigger
+        let rests = (order.rests() || order_type.is_stop()) && !matched.taker_cancelled;
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(user_id, stock_id, quantity, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let filled: i64 = fills.iter().map(|f| f.quantity).sum();
+
+        // Serialise orders per user so two orders can't spend the same funds
+        // or offer the same shares
+        let _ = sqlx::query!(
+            "SELECT user_id FROM users WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, stock_id, quantity, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let group_id = match group {
+            Some(NewGroup {
+                group_type,
+                take_profit,
+                stop_loss,
+            }) => Some(
+                sqlx::query!(
+                    "INSERT INTO order_groups (group_type, take_profit_price, stop_loss_price) VALUES ($1, $2, $3) RETURNING group_id",
+                    group_type as i64,
+                    take_profit,
+                    stop_loss
+                )
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!(user_id, stock_id, quantity, "{}", &e);
+                    AppError::DatabaseError
+                })?
+                .group_id,
+            ),
+            None => group_id,
+        };
+
+        // A grouped stop shares the funds or shares of its other legs, which
+        // it only needs once they've been cancelled by its trigger
+        let grouped_stop = order_type.is_stop() && group_id.is_some();
+        if is_buy && !grouped_stop {
+            let fees = Self::fee_schedule(&mut *tx, user_id).await?;
+            let cost: i64 = fills
+                .iter()
+                .map(|f| f.quantity * f.price + fees.fee(f.quantity * f.price, false))
+                .sum::<i64>()
+                + match price {
+                    Some(price) if rests => {
+                        let notional = (quantity - filled) * price;
+                        notional + fees.max_fee(notional)
+                    }
+                    _ => 0,
+                };
+            if Self::wallet_balance(&mut *tx, user_id).await?.available < cost {
+                return Err(AppError::InsufficientFunds);
+            }
+        } else if !is_buy
+            && !grouped_stop
+            && Self::stock_position(&mut *tx, user_id, stock_id).await? < quantity
+        {
+            return Err(AppError::InsufficientShares);
+        }
+
+        let status = if filled == quantity {
+            OrderStatus::Completed
+        } else if matched.taker_cancelled && order_type == OrderType::Limit {
+            OrderStatus::Cancelled
+        } else if rests
+            || (order_type, time_in_force) == (OrderType::Market, TimeInForce::ImmediateOrCancel)
+        {
+            if filled > 0 {
+                OrderStatus::PartiallyComplete
+            } else if rests {
+                OrderStatus::InProgress
+            } else {
+                OrderStatus::Cancelled
+            }
+        } else {
+            OrderStatus::Expired
+        };
+        let expiry_reason = (filled < quantity && !rests).then_some(if matched.taker_cancelled {
+            ExpiryReason::SelfTradePrevented
+        } else if time_in_force == TimeInForce::FillOrKill {
+            ExpiryReason::NotFullyFillable
+        } else {
+            ExpiryReason::NotFilledImmediately
+        });
+
+        let order_id = sqlx::query!(
+            "INSERT INTO orders (user_id, stock_id, is_buy, order_type, amount, limit_price, order_status, time_in_force, expires_at, expiry_reason, trigger_price, parent_order, self_trade_prevention, display_quantity, group_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) RETURNING order_id",
+            user_id,
+            stock_id,
+            is_buy,
+            order_type as i64,
+            quantity,
+            price,
+            status as i64,
+            time_in_force as i64,
+            expires_at.map(|at| at.naive_utc()),
+            expiry_reason.map(|r| r as i64),
+            trigger_price,
+            parent_order,
+            self_trade_prevention as i64,
+            display_quantity,
+            group_id,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, stock_id, quantity, "{}", &e);
+            AppError::DatabaseError
+        })?.order_id;
+
+        // Reserve a run of tape sequence numbers for the fills, the stock row
+        // lock keeps them gapless and in commit order
+        let mut seq = sqlx::query!(
+            "UPDATE stocks SET last_trade_seq = last_trade_seq + $1 WHERE stock_id = $2 RETURNING last_trade_seq",
+            fills.len() as i64,
+            stock_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(user_id, stock_id, quantity, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .last_trade_seq
+            - fills.len() as i64;
+        let first_seq = seq + 1;
+
+        for fill in fills {
+            let (sell_order, buy_order) = if is_buy {
+                (fill.order_id, order_id)
+            } else {
+                (order_id, fill.order_id)
+            };
+            seq += 1;
+            let _ = sqlx::query!(
+                r#"
+                INSERT INTO trades (stock_id, seq, sell_order, buy_order, amount, price, aggressor_is_buy) VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+                stock_id,
+                seq,
+                sell_order,
+                buy_order,
+                fill.quantity,
+                fill.price,
+                is_buy
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(user_id, stock_id, quantity, "{}", &e);
+                AppError::DatabaseError
+            })?;
+
+            // A refreshed iceberg slice goes to the back of the queue
+            let _ = sqlx::query!(
+                r#"
+                UPDATE orders
+                SET order_status = $1, queued_at = CASE WHEN $2 THEN clock_timestamp() ELSE queued_at END
+                WHERE order_id = $3
+        "#,
+                if fill.remaining == 0 {
+                    OrderStatus::Completed
+                } else {
+                    OrderStatus::PartiallyComplete
+                } as i64,
+                fill.refreshed,
+                fill.order_id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(user_id, stock_id, quantity, "{}", &e);
+                AppError::DatabaseError
+            })?;
+        }
+
+        if !matched.cancelled.is_empty() {
+            let _ = sqlx::query!(
+                "UPDATE orders SET order_status = $1, expiry_reason = $2 WHERE order_id = ANY($3)",
+                OrderStatus::Cancelled as i64,
+                ExpiryReason::SelfTradePrevented as i64,
+                &matched.cancelled
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(user_id, stock_id, quantity, "{}", &e);
+                AppError::DatabaseError
+            })?;
+        }
+
+        let mut traded: Vec<i64> = fills.iter().map(|f| f.order_id).collect();
+        if filled > 0 {
+            traded.push(order_id);
+        }
+        let cancelled = Self::cancel_siblings(&mut *tx, &traded).await?;
+        let filled_brackets = Self::filled_brackets(&mut *tx, &traded).await?;
+
+        // The stop leg covers whatever of the order is left open
+        let stop_leg = match stop {
+            Some(stop) if rests && filled < quantity => Some(
+                sqlx::query!(
+                    "INSERT INTO orders (user_id, stock_id, is_buy, order_type, amount, limit_price, order_status, time_in_force, expires_at, trigger_price, parent_order, self_trade_prevention, group_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) RETURNING order_id",
+                    stop.user_id,
+                    stop.stock_id,
+                    stop.is_buy,
+                    stop.order_type as i64,
+                    quantity - filled,
+                    stop.price,
+                    OrderStatus::InProgress as i64,
+                    stop.time_in_force as i64,
+                    stop.expires_at.map(|at| at.naive_utc()),
+                    stop.trigger_price,
+                    stop.parent_order.unwrap_or(order_id),
+                    stop.self_trade_prevention as i64,
+                    group_id,
+                )
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!(user_id, stock_id, quantity, "{}", &e);
+                    AppError::DatabaseError
+                })?
+                .order_id,
+            ),
+            _ => None,
+        };
+
+        if !fills.is_empty() {
+            Self::roll_up_candles(&mut *tx, stock_id, first_seq, seq).await?;
+            Self::post_trades(&mut *tx, stock_id, first_seq, seq).await?;
+            Self::book_fees(&mut *tx, stock_id, first_seq, seq).await?;
+        }
+
+        tx.commit().await.map_err(|e| {
+            error!(user_id, stock_id, quantity, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        Ok(CreatedOrder {
+            order_id,
+            cancelled,
+            filled_brackets,
+            stop_leg,
+        })
+    }
+
+    /// Stocks that aren't simply trading continuously: in a call phase,
+    /// halted or watched by a circuit breaker
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_trading_states(&self) -> Result<Vec<TradingState>, AppError> {
+        let data = sqlx::query!(
+            r#"
+            SELECT stock_id, trading_phase, halted, halted_until, breaker_move_percent, breaker_window_secs, breaker_halt_secs
+            FROM stocks
+            WHERE trading_phase != $1 OR halted OR halted_until IS NOT NULL OR breaker_move_percent IS NOT NULL
+            "#,
+            TradingPhase::Continuous as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        Ok(data
+            .into_iter()
+            .map(|r| TradingState {
+                stock_id: r.stock_id,
+                phase: TradingPhase::from(r.trading_phase),
+                halted: r.halted,
+                halted_until: r.halted_until.map(|at| at.and_utc()),
+                circuit_breaker: match (
+                    r.breaker_move_percent,
+                    r.breaker_window_secs,
+                    r.breaker_halt_secs,
+                ) {
+                    (Some(max_move_percent), Some(window_secs), Some(halt_secs)) => {
+                        Some(CircuitBreaker {
+                            max_move_percent,
+                            window_secs,
+                            halt_secs,
+                        })
+                    }
+                    _ => None,
+                },
+            })
+            .collect())
+    }
+
+    /// Record an admin halt and/or the end of a circuit breaker cool-down,
+    /// clearing both resumes trading
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn set_trading_halt(
+        &self,
+        stock_id: i64,
+        halted: bool,
+        halted_until: Option<DateTime<Utc>>,
+    ) -> Result<(), AppError> {
+        let _ = sqlx::query!(
+            "UPDATE stocks SET halted = $1, halted_until = $2 WHERE stock_id = $3 RETURNING stock_id",
+            halted,
+            halted_until.map(|at| at.naive_utc()),
+            stock_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?;
+
+        Ok(())
+    }
+
+    /// Stocks whose circuit breaker cool-down is over by `now`, leaving
+    /// those an admin has halted as well
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_ended_halts(&self, now: DateTime<Utc>) -> Result<Vec<i64>, AppError> {
+        let stock_ids = sqlx::query!(
+            "SELECT stock_id FROM stocks WHERE NOT halted AND halted_until <= $1",
+            now.naive_utc()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("{}", &e);
+            AppError::DatabaseError
+        })?
+        .into_iter()
+        .map(|r| r.stock_id)
+        .collect();
+
+        Ok(stock_ids)
+    }
+
+    /// Set or, with `None`, turn off the circuit breaker of a stock
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn set_circuit_breaker(
+        &self,
+        stock_id: i64,
+        circuit_breaker: Option<CircuitBreaker>,
+    ) -> Result<(), AppError> {
+        let _ = sqlx::query!(
+            "UPDATE stocks SET breaker_move_percent = $1, breaker_window_secs = $2, breaker_halt_secs = $3 WHERE stock_id = $4 RETURNING stock_id",
+            circuit_breaker.map(|b| b.max_move_percent),
+            circuit_breaker.map(|b| b.window_secs),
+            circuit_breaker.map(|b| b.halt_secs),
+            stock_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?;
+
+        Ok(())
+    }
+
+    /// Price of the latest print in a stock, the reference for breaking ties
+    /// between auction prices
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_last_price(&self, stock_id: i64) -> Result<Option<i64>, AppError> {
+        let last_price = sqlx::query!(
+            r#"
+            SELECT (SELECT t.price FROM trades t WHERE t.stock_id = s.stock_id AND t.seq IS NOT NULL ORDER BY t.seq DESC LIMIT 1) AS last_price
+            FROM stocks s
+            WHERE s.stock_id = $1
+           "#,
+            stock_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?
+        .last_price;
+
+        Ok(last_price)
+    }
+
+    /// Move a stock into a new trading phase without an auction
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn set_trading_phase(
+        &self,
+        stock_id: i64,
+        phase: TradingPhase,
+    ) -> Result<(), AppError> {
+        let _ = sqlx::query!(
+            "UPDATE stocks SET trading_phase = $1 WHERE stock_id = $2 RETURNING stock_id",
+            phase as i64,
+            stock_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?;
+
+        Ok(())
+    }
+
+    /// End the call phase `ended` of a stock by persisting its auction and
+    /// moving it into `phase`. The auction's prints go on the tape without an
+    /// aggressor and a closing auction's price becomes the official close.
+    /// Orders self-trade prevention took out of it are cancelled, and an
+    /// auction they leave nothing to cross isn't recorded. Returns the other
+    /// legs of traded orders' groups that got cancelled
+    /// and the brackets whose entry filled, like [`DB::create_order`].
+    #[tracing::instrument(skip(self, uncross), fields(service.name = "db", db.operation.name = "INSERT"))]
+    pub async fn record_uncross(
+        &self,
+        stock_id: i64,
+        ended: TradingPhase,
+        phase: TradingPhase,
+        uncross: &Uncross,
+    ) -> Result<(Vec<i64>, Vec<i64>), AppError> {
+        let mut tx = self.pool.begin().await.map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        let crosses = &uncross.crosses;
+        let mut seq = sqlx::query!(
+            "UPDATE stocks SET last_trade_seq = last_trade_seq + $1, trading_phase = $2 WHERE stock_id = $3 RETURNING last_trade_seq",
+            crosses.len() as i64,
+            phase as i64,
+            stock_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .ok_or(AppError::StockNotFound)?
+        .last_trade_seq
+            - crosses.len() as i64;
+        let first_seq = seq + 1;
+
+        for cross in crosses {
+            seq += 1;
+            let _ = sqlx::query!(
+                "INSERT INTO trades (stock_id, seq, sell_order, buy_order, amount, price) VALUES ($1, $2, $3, $4, $5, $6)",
+                stock_id,
+                seq,
+                cross.sell_order,
+                cross.buy_order,
+                cross.quantity,
+                uncross.price
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(stock_id, "{}", &e);
+                AppError::DatabaseError
+            })?;
+        }
+
+        let traded: Vec<i64> = crosses
+            .iter()
+            .flat_map(|c| [c.buy_order, c.sell_order])
+            .collect();
+        let _ = sqlx::query!(
+            r#"
+            UPDATE orders o
+            SET order_status = CASE WHEN o.amount <= (SELECT SUM(t.amount) FROM trades t WHERE t.buy_order = o.order_id OR t.sell_order = o.order_id) THEN $1::BIGINT ELSE $2::BIGINT END,
+                queued_at = CASE WHEN o.order_id = ANY($3) THEN clock_timestamp() ELSE o.queued_at END
+            WHERE o.order_id = ANY($4)
+            "#,
+            OrderStatus::Completed as i64,
+            OrderStatus::PartiallyComplete as i64,
+            &uncross.refreshed,
+            &traded
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+
+        if !uncross.cancelled.is_empty() {
+            let _ = sqlx::query!(
+                "UPDATE orders SET order_status = $1, expiry_reason = $2 WHERE order_id = ANY($3)",
+                OrderStatus::Cancelled as i64,
+                ExpiryReason::SelfTradePrevented as i64,
+                &uncross.cancelled
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(stock_id, "{}", &e);
+                AppError::DatabaseError
+            })?;
+        }
+
+        let cancelled = Self::cancel_siblings(&mut *tx, &traded).await?;
+        let filled_brackets = Self::filled_brackets(&mut *tx, &traded).await?;
+
+        if !crosses.is_empty() {
+            let _ = sqlx::query!(
+                "INSERT INTO auctions (stock_id, phase, price, volume) VALUES ($1, $2, $3, $4)",
+                stock_id,
+                ended as i64,
+                uncross.price,
+                uncross.volume
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!(stock_id, "{}", &e);
+                AppError::DatabaseError
+            })?;
+
+            Self::roll_up_candles(&mut *tx, stock_id, first_seq, seq).await?;
+            Self::post_trades(&mut *tx, stock_id, first_seq, seq).await?;
+            Self::book_fees(&mut *tx, stock_id, first_seq, seq).await?;
+        }
+
+        tx.commit().await.map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?;
+        Ok((cancelled, filled_brackets))
+    }
+
+    /// Stocks with open GTD orders due to expire by `now`
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "SELECT"))]
+    pub async fn get_expiring_stocks(&self, now: DateTime<Utc>) -> Result<Vec<i64>, AppError> {
+        let stock_ids = sqlx::query!(
+            "SELECT DISTINCT stock_id FROM orders WHERE expires_at <= $1 AND order_status IN ($2, $3)",
+            now.naive_utc(),
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!("{}", &e);
+            AppError::DatabaseError
+        })?
+        .into_iter()
+        .map(|i| i.stock_id)
+        .collect();
+
+        Ok(stock_ids)
+    }
+
+    /// Expire the open GTD orders of a stock that are due by `now`,
+    /// returning their ids
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "UPDATE"))]
+    pub async fn expire_orders(
+        &self,
+        stock_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<i64>, AppError> {
+        let order_ids = sqlx::query!(
+            r#"
+            UPDATE orders SET order_status = $1, expiry_reason = $2
+            WHERE stock_id = $3 AND expires_at <= $4 AND order_status IN ($5, $6)
+            RETURNING order_id
+            "#,
+            OrderStatus::Expired as i64,
+            ExpiryReason::ExpiryTimeReached as i64,
+            stock_id,
+            now.naive_utc(),
+            OrderStatus::InProgress as i64,
+            OrderStatus::PartiallyComplete as i64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(stock_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .into_iter()
+        .map(|i| i.order_id)
+        .collect();
+
+        Ok(order_ids)
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "db", db.operation.name = "INSERT"))]
+    pub async fn cancel_order(&self, user_id: i64, stock_tx_id: i64) -> Result<(), AppError> {
+        // Someone else's order is reported exactly like a missing one
+        let _ = sqlx::query!(
+            r#"
+            UPDATE orders SET order_status = $1 WHERE order_id = $2 AND order_type != $3 AND order_status > 0 AND (user_id = $4 OR $5) RETURNING order_id
+            "#,
+            OrderStatus::Cancelled as i64,
+            stock_tx_id,
+            OrderType::Market as i64,
+            user_id,
+            self.cancel_any_order,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| {
+            error!(user_id, stock_tx_id, "{}", &e);
+            AppError::DatabaseError
+        })?
+        .map(|i| i.order_id)
+        .ok_or(AppError::StockTransactionNotFound)?;
+
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub struct DbUser {
     pub user_id: i64,
     pub user_name: String,
     pub password: String,
+    pub fee_tier: String,
+    pub is_admin: bool,
     pub created_at: NaiveDateTime,
 }
 
+#[derive(Debug)]
+pub struct DbOpenOrder {
+    pub order_id: i64,
+    pub user_id: i64,
+    pub stock_id: i64,
+    pub is_buy: bool,
+    pub limit_price: i64,
+    pub filled: i64,
+    pub remaining: i64,
+    pub display_quantity: Option<i64>,
+    pub self_trade_prevention: SelfTradePrevention,
+}
+
+/// A persisted incoming order and what it set off
+#[derive(Debug)]
+pub struct CreatedOrder {
+    pub order_id: i64,
+    /// Other legs of the groups of the orders that traded
+    pub cancelled: Vec<i64>,
+    /// Brackets whose entry this completed, ready for their exits
+    pub filled_brackets: Vec<i64>,
+    /// Stop leg placed along with the order for what it left open
+    pub stop_leg: Option<i64>,
+}
+
+/// Trading state of a stock to restore its book with
+#[derive(Debug)]
+pub struct TradingState {
+    pub stock_id: i64,
+    pub phase: TradingPhase,
+    pub halted: bool,
+    pub halted_until: Option<DateTime<Utc>>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+#[derive(Debug)]
+pub struct DbStopOrder {
+    pub order_id: i64,
+    pub stock_id: i64,
+    pub is_buy: bool,
+    pub trigger_price: i64,
+}
+
 #[derive(Debug, sqlx::FromRow)]
 struct DBStockPrice {
     stock_id: i64,
     stock_name: String,
-    price: Option<i64>,
+    best_bid: Option<i64>,
+    best_ask: Option<i64>,
+    last_price: Option<i64>,
+    previous_close: Option<i64>,
+    volume_24h: i64,
+    trading_halted: bool,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -557,26 +2631,63 @@ struct DBStockPortfolio {
     stock_id: i64,
     stock_name: String,
     quantity_owned: BigDecimal,
+    last_price: Option<i64>,
+}
+
+/// Position after a row of a history, given as a page's `next_cursor`: the
+/// row's time in microseconds followed by the rest of its sort key
+fn encode_cursor(time_stamp: NaiveDateTime, keys: &[i64]) -> String {
+    std::iter::once(time_stamp.and_utc().timestamp_micros())
+        .chain(keys.iter().copied())
+        .map(|k| k.to_string())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn decode_cursor<const N: usize>(cursor: &str) -> Result<(NaiveDateTime, [i64; N]), AppError> {
+    let keys = cursor
+        .split('_')
+        .map(|k| k.parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AppError::BadRequest)?;
+    let (&micros, rest) = keys.split_first().ok_or(AppError::BadRequest)?;
+    let time_stamp = DateTime::from_timestamp_micros(micros)
+        .ok_or(AppError::BadRequest)?
+        .naive_utc();
+    Ok((
+        time_stamp,
+        rest.try_into().map_err(|_| AppError::BadRequest)?,
+    ))
 }
 
 #[derive(Debug, sqlx::FromRow)]
 struct DBWalletTransaction {
     wallet_tx_id: i64,
-    stock_tx_id: i64,
-    seller_id: i64,
+    kind: i64,
+    stock_tx_id: Option<i64>,
+    is_debit: bool,
     amount: i64,
     time_stamp: NaiveDateTime,
 }
 
 #[derive(Debug, sqlx::FromRow)]
 struct DBStockTransaction {
+    is_order: bool,
     stock_tx_id: i64,
     parent_stock_tx_id: i64,
     wallet_tx_id: i64,
     stock_id: i64,
     order_status: OrderStatus,
-    stock_price: Option<i64>,
-    limit_price: Option<i64>,
+    is_buy: bool,
+    order_type: OrderType,
+    stock_price: i64,
     quantity: i64,
-    time_stamp: Option<NaiveDateTime>,
+    time_in_force: TimeInForce,
+    expiry_reason: Option<i64>,
+    self_trade_prevention: SelfTradePrevention,
+    display_quantity: Option<i64>,
+    order_group: Option<i64>,
+    trigger_price: Option<i64>,
+    triggered_at: Option<NaiveDateTime>,
+    time_stamp: NaiveDateTime,
 }
@@ -0,0 +1,674 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex as AsyncMutex;
+use tracing::{error, info};
+
+use crate::{
+    book::{Fill, Match, OrderBook, RestingOrder, StopOrder},
+    db::{CreatedOrder, DB},
+    types::{
+        AppError, AuctionIndicative, CircuitBreaker, OrderGroupType, OrderType,
+        SelfTradePrevention, Side, TimeInForce, TradingPhase,
+    },
+};
+
+/// An order as submitted to the matching engine
+#[derive(Debug, Clone)]
+pub struct NewOrder {
+    pub user_id: i64,
+    pub stock_id: i64,
+    pub is_buy: bool,
+    pub order_type: OrderType,
+    pub quantity: i64,
+    pub price: Option<i64>,
+    pub time_in_force: TimeInForce,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Only set on stop orders
+    pub trigger_price: Option<i64>,
+    /// The stop order this order was converted from, or the first leg of
+    /// its group
+    pub parent_order: Option<i64>,
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Slice size shown on the book for iceberg orders
+    pub display_quantity: Option<i64>,
+    /// The OCO pair or bracket this order is a leg of
+    pub group_id: Option<i64>,
+}
+
+/// An OCO pair or bracket created along with its first leg
+#[derive(Debug, Clone, Copy)]
+pub struct NewGroup {
+    pub group_type: OrderGroupType,
+    /// Exit prices of a bracket
+    pub take_profit: Option<i64>,
+    pub stop_loss: Option<i64>,
+}
+
+impl NewOrder {
+    /// Whether whatever doesn't fill on arrival rests on the book
+    pub fn rests(&self) -> bool {
+        self.order_type == OrderType::Limit
+            && matches!(
+                self.time_in_force,
+                TimeInForce::GoodTilCancelled | TimeInForce::GoodTilDate
+            )
+    }
+}
+
+/// How often the engine looks for GTD orders past their expiry
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+type Books = Arc<Mutex<HashMap<i64, Arc<AsyncMutex<OrderBook>>>>>;
+
+/// Matching engine holding one in-memory [`OrderBook`] per stock.
+///
+/// Each book is guarded by its own lock so orders for different stocks never
+/// wait on each other. Matching takes the fills off the book in memory and
+/// the fills are then persisted; if the DB commit fails the match is undone.
+#[derive(Clone)]
+pub struct Engine {
+    db: DB,
+    books: Books,
+}
+
+impl Engine {
+    /// Rebuild every order book from the open and stop orders in the
+    /// database, place any bracket exits that were due when it went down and
+    /// start expiring GTD orders in the background
+    pub async fn init(db: DB) -> Result<Self, AppError> {
+        let mut books: HashMap<i64, OrderBook> = HashMap::new();
+        let open_orders = db.get_open_orders().await?;
+        info!(orders = open_orders.len(), "rebuilding order books");
+
+        for o in open_orders {
+            books.entry(o.stock_id).or_default().insert(
+                o.is_buy,
+                o.limit_price,
+                RestingOrder::new(
+                    o.order_id,
+                    o.user_id,
+                    o.filled,
+                    o.remaining,
+                    o.display_quantity,
+                    o.self_trade_prevention,
+                ),
+            );
+        }
+        for state in db.get_trading_states().await? {
+            let book = books.entry(state.stock_id).or_default();
+            book.phase = state.phase;
+            book.halted = state.halted;
+            book.halted_until = state.halted_until;
+            book.circuit_breaker = state.circuit_breaker;
+        }
+        for s in db.get_stop_orders().await? {
+            books.entry(s.stock_id).or_default().insert_stop(StopOrder {
+                order_id: s.order_id,
+                is_buy: s.is_buy,
+                trigger_price: s.trigger_price,
+            });
+        }
+
+        let engine = Engine {
+            db,
+            books: Arc::new(Mutex::new(
+                books
+                    .into_iter()
+                    .map(|(stock_id, book)| (stock_id, Arc::new(AsyncMutex::new(book))))
+                    .collect(),
+            )),
+        };
+
+        for (group_id, stock_id) in engine.db.get_unplaced_brackets().await? {
+            let book = engine.book(stock_id);
+            let mut book = book.lock().await;
+            engine
+                .settle(stock_id, &mut book, vec![], vec![group_id])
+                .await;
+        }
+
+        let sweeper = engine.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = sweeper.expire_orders(Utc::now()).await {
+                    error!("failed to expire orders: {e:?}");
+                }
+                if let Err(e) = sweeper.end_halts(Utc::now()).await {
+                    error!("failed to end halts: {e:?}");
+                }
+            }
+        });
+
+        Ok(engine)
+    }
+
+    fn book(&self, stock_id: i64) -> Arc<AsyncMutex<OrderBook>> {
+        self.books
+            .lock()
+            .expect("books lock to not be poisoned")
+            .entry(stock_id)
+            .or_default()
+            .clone()
+    }
+
+    /// Match an incoming order against the book, persist the resulting
+    /// fills and rest whatever is left of a limit order. Stop orders are
+    /// parked until a trade reaches their trigger instead.
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn place_order(&self, order: NewOrder) -> Result<(), AppError> {
+        let book = self.book(order.stock_id);
+        let mut book = book.lock().await;
+
+        if order.order_type.is_stop() {
+            return self.park_stop(&mut book, &order).await;
+        }
+
+        let (created, fills) = self.execute(&mut book, &order, None, None).await?;
+        self.settle(
+            order.stock_id,
+            &mut book,
+            prices(&fills),
+            created.filled_brackets,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Place a limit order along with a stop leg, both in one go. The stop
+    /// leg points back at the limit leg and covers whatever of it is left
+    /// open: a partial fill shrinks the stop leg, a complete one cancels it,
+    /// and the stop leg triggering cancels the limit leg.
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn place_oco(&self, limit: NewOrder, stop: NewOrder) -> Result<(), AppError> {
+        let book = self.book(limit.stock_id);
+        let mut book = book.lock().await;
+
+        let group = NewGroup {
+            group_type: OrderGroupType::OneCancelsOther,
+            take_profit: None,
+            stop_loss: None,
+        };
+        let (created, fills) = self
+            .execute(&mut book, &limit, Some(group), Some(&stop))
+            .await?;
+        self.settle(
+            limit.stock_id,
+            &mut book,
+            prices(&fills),
+            created.filled_brackets,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Place the entry of a bracket. Once it has filled completely its
+    /// take-profit limit and stop-loss stop exits are placed for the same
+    /// quantity on the other side, as an OCO pair pointing back at it.
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn place_bracket(
+        &self,
+        entry: NewOrder,
+        take_profit: i64,
+        stop_loss: i64,
+    ) -> Result<(), AppError> {
+        let book = self.book(entry.stock_id);
+        let mut book = book.lock().await;
+
+        let group = NewGroup {
+            group_type: OrderGroupType::Bracket,
+            take_profit: Some(take_profit),
+            stop_loss: Some(stop_loss),
+        };
+        let (created, fills) = self.execute(&mut book, &entry, Some(group), None).await?;
+        self.settle(
+            entry.stock_id,
+            &mut book,
+            prices(&fills),
+            created.filled_brackets,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn park_stop(&self, book: &mut OrderBook, order: &NewOrder) -> Result<(), AppError> {
+        if book.is_halted(Utc::now()) {
+            return Err(AppError::TradingHalted);
+        }
+        let order_id = self
+            .db
+            .create_order(order, &Match::default(), None, None)
+            .await?
+            .order_id;
+        book.insert_stop(StopOrder {
+            order_id,
+            is_buy: order.is_buy,
+            trigger_price: order.trigger_price.expect("stop orders to have a trigger"),
+        });
+        Ok(())
+    }
+
+    /// Match and persist an order, along with the group it starts and a
+    /// stop leg parked for whatever of it is left open. During a call phase
+    /// only orders that can wait for the auction are accepted, and they rest
+    /// without matching.
+    async fn execute(
+        &self,
+        book: &mut OrderBook,
+        order: &NewOrder,
+        group: Option<NewGroup>,
+        stop: Option<&NewOrder>,
+    ) -> Result<(CreatedOrder, Vec<Fill>), AppError> {
+        if book.is_halted(Utc::now()) {
+            return Err(AppError::TradingHalted);
+        }
+        if book.phase.is_call() && !order.rests() {
+            return Err(AppError::BadRequest);
+        }
+        let mut matched = if book.phase.is_call() {
+            Match::default()
+        } else {
+            book.match_order(
+                order.is_buy,
+                order.user_id,
+                order.quantity,
+                order.price,
+                order.self_trade_prevention,
+            )
+        };
+        if order.time_in_force == TimeInForce::FillOrKill && matched.filled() < order.quantity {
+            book.undo(order.is_buy, &matched);
+            matched = Match::default();
+        }
+        let created = match self.db.create_order(order, &matched, group, stop).await {
+            Ok(created) => created,
+            Err(e) => {
+                book.undo(order.is_buy, &matched);
+                return Err(e);
+            }
+        };
+        remove_orders(book, &created.cancelled);
+
+        let remaining = order.quantity - matched.filled();
+        if order.rests() && !matched.taker_cancelled && remaining > 0 {
+            book.insert(
+                order.is_buy,
+                order.price.expect("limit orders to have a price"),
+                RestingOrder::new(
+                    created.order_id,
+                    order.user_id,
+                    order.quantity - remaining,
+                    remaining,
+                    order.display_quantity,
+                    order.self_trade_prevention,
+                ),
+            );
+        }
+        if let (Some(stop), Some(order_id)) = (stop, created.stop_leg) {
+            book.insert_stop(StopOrder {
+                order_id,
+                is_buy: stop.is_buy,
+                trigger_price: stop.trigger_price.expect("stop orders to have a trigger"),
+            });
+        }
+
+        Ok((created, matched.fills))
+    }
+
+    /// Follow up on executions: place the exits of the brackets whose entry
+    /// filled and convert the stops reached by the traded `prices` into
+    /// market or limit orders, all of which can in turn set off more. The
+    /// order that started it has already gone through, so a stop that can't
+    /// be executed is marked failed rather than reported back. Stops stay
+    /// dormant during a call phase since they couldn't execute.
+    ///
+    /// Prices that trip the circuit breaker halt the stock and end the
+    /// chain there: reached stops stay parked and bracket exits are left for
+    /// when trading resumes.
+    async fn settle(
+        &self,
+        stock_id: i64,
+        book: &mut OrderBook,
+        mut prices: Vec<i64>,
+        mut brackets: Vec<i64>,
+    ) {
+        while !prices.is_empty() || !brackets.is_empty() {
+            let now = Utc::now();
+            if let Some(until) = book.trip_circuit_breaker(now, &prices) {
+                info!(stock_id, %until, "circuit breaker tripped");
+                if let Err(e) = self
+                    .db
+                    .set_trading_halt(stock_id, book.halted, Some(until))
+                    .await
+                {
+                    error!(stock_id, "failed to record halt: {e:?}");
+                }
+            }
+            if book.is_halted(now) {
+                return;
+            }
+            let (mut next_prices, mut next_brackets) = (vec![], vec![]);
+            for group_id in brackets {
+                match self.place_exits(book, group_id).await {
+                    Ok(Some((created, exit_fills))) => {
+                        next_prices.extend(self::prices(&exit_fills));
+                        next_brackets.extend(created.filled_brackets);
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!(group_id, "failed to place bracket exits: {e:?}"),
+                }
+            }
+
+            let triggered = match (prices.iter().min(), prices.iter().max()) {
+                (Some(&low), Some(&high)) if !book.phase.is_call() => {
+                    book.take_triggered(low, high)
+                }
+                _ => vec![],
+            };
+            for stop in triggered {
+                let result = match self.db.trigger_stop(stop.order_id).await {
+                    Ok((order, cancelled)) => {
+                        remove_orders(book, &cancelled);
+                        self.execute(book, &order, None, None).await
+                    }
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok((created, stop_fills)) => {
+                        next_prices.extend(self::prices(&stop_fills));
+                        next_brackets.extend(created.filled_brackets);
+                    }
+                    Err(e) => {
+                        error!(
+                            order_id = stop.order_id,
+                            "failed to execute stop order: {e:?}"
+                        );
+                        if let Err(e) = self.db.fail_order(stop.order_id).await {
+                            error!(order_id = stop.order_id, "{e:?}");
+                        }
+                    }
+                }
+            }
+            (prices, brackets) = (next_prices, next_brackets);
+        }
+    }
+
+    /// Place the exits of a bracket whose entry has filled, unless that
+    /// already happened. The stop-loss goes in with the take-profit and only
+    /// covers what the take-profit doesn't fill straight away.
+    async fn place_exits(
+        &self,
+        book: &mut OrderBook,
+        group_id: i64,
+    ) -> Result<Option<(CreatedOrder, Vec<Fill>)>, AppError> {
+        let Some((stop_loss, take_profit)) = self.db.get_bracket_exits(group_id).await? else {
+            return Ok(None);
+        };
+        self.execute(book, &take_profit, None, Some(&stop_loss))
+            .await
+            .map(Some)
+    }
+
+    /// Take every GTD order whose expiry is at or before `now` off the book
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn expire_orders(&self, now: DateTime<Utc>) -> Result<(), AppError> {
+        for stock_id in self.db.get_expiring_stocks(now).await? {
+            let book = self.book(stock_id);
+            let mut book = book.lock().await;
+
+            for order_id in self.db.expire_orders(stock_id, now).await? {
+                book.remove(order_id);
+                book.remove_stop(order_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Change the quantity and/or price of a resting limit order. Shrinking
+    /// it keeps its place in the queue, anything else sends it to the back
+    /// of its (new) price level. A new price that would trade on arrival,
+    /// even against the user's own orders, is rejected rather than matched.
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn modify_order(
+        &self,
+        user_id: i64,
+        stock_tx_id: i64,
+        quantity: Option<i64>,
+        price: Option<i64>,
+    ) -> Result<(), AppError> {
+        let stock_id = self.db.get_order_stock(Some(user_id), stock_tx_id).await?;
+        let book = self.book(stock_id);
+        let mut book = book.lock().await;
+
+        if book.is_halted(Utc::now()) {
+            return Err(AppError::TradingHalted);
+        }
+        let Some((is_buy, old_price, resting)) = book.get(stock_tx_id) else {
+            return Err(AppError::StockTransactionNotFound);
+        };
+        if resting.user_id != user_id {
+            return Err(AppError::StockTransactionNotFound);
+        }
+        let (old_remaining, new_price) = (resting.remaining, price.unwrap_or(old_price));
+        if new_price != old_price && !book.phase.is_call() && book.crosses(is_buy, new_price) {
+            return Err(AppError::BadRequest);
+        }
+
+        let (filled, remaining) = self
+            .db
+            .amend_order(user_id, stock_tx_id, quantity, price)
+            .await?;
+        if new_price == old_price && remaining <= old_remaining {
+            book.reduce(stock_tx_id, remaining);
+        } else {
+            let (display_quantity, self_trade_prevention) = book
+                .remove(stock_tx_id)
+                .map(|o| (o.display_quantity, o.self_trade_prevention))
+                .unwrap_or_default();
+            book.insert(
+                is_buy,
+                new_price,
+                RestingOrder::new(
+                    stock_tx_id,
+                    user_id,
+                    filled,
+                    remaining,
+                    display_quantity,
+                    self_trade_prevention,
+                ),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Move a stock through its trading session: from the pre-open call
+    /// into continuous trading through the opening auction, into the closing
+    /// call and back to pre-open through the closing auction
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn set_trading_phase(
+        &self,
+        stock_id: i64,
+        phase: TradingPhase,
+    ) -> Result<(), AppError> {
+        let book = self.book(stock_id);
+        let mut book = book.lock().await;
+
+        if book.is_halted(Utc::now()) {
+            return Err(AppError::TradingHalted);
+        }
+        let ended = book.phase;
+        let valid = matches!(
+            (ended, phase),
+            (TradingPhase::PreOpen, TradingPhase::Continuous)
+                | (TradingPhase::Continuous, TradingPhase::PreClose)
+                | (TradingPhase::PreClose, TradingPhase::PreOpen)
+        );
+        if !valid {
+            return Err(AppError::BadRequest);
+        }
+
+        // Read under the book lock so no trade can land in between
+        let reference = self.db.get_last_price(stock_id).await?;
+        let Some(uncross) = ended.is_call().then(|| book.uncross(reference)).flatten() else {
+            self.db.set_trading_phase(stock_id, phase).await?;
+            book.phase = phase;
+            return Ok(());
+        };
+        let (cancelled, brackets) = self
+            .db
+            .record_uncross(stock_id, ended, phase, &uncross)
+            .await?;
+        book.apply_uncross(&uncross);
+        remove_orders(&mut book, &cancelled);
+        book.phase = phase;
+        let prices = if uncross.volume > 0 {
+            vec![uncross.price]
+        } else {
+            vec![]
+        };
+        self.settle(stock_id, &mut book, prices, brackets).await;
+
+        Ok(())
+    }
+
+    /// Price, volume and imbalance the auction of a stock in a call phase
+    /// would uncross with now
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn auction_indicative(&self, stock_id: i64) -> Result<AuctionIndicative, AppError> {
+        let book = self.book(stock_id);
+        let book = book.lock().await;
+
+        let reference = self.db.get_last_price(stock_id).await?;
+        let uncross = book
+            .phase
+            .is_call()
+            .then(|| book.uncross(reference))
+            .flatten();
+        let imbalance = uncross.as_ref().map_or(0, |u| u.imbalance);
+        Ok(AuctionIndicative {
+            stock_id: stock_id.to_string(),
+            phase: book.phase,
+            indicative_price: uncross.as_ref().filter(|u| u.volume > 0).map(|u| u.price),
+            matched_volume: uncross.as_ref().map_or(0, |u| u.volume),
+            imbalance: imbalance.abs(),
+            imbalance_side: match imbalance {
+                0 => None,
+                i if i > 0 => Some(Side::Buy),
+                _ => Some(Side::Sell),
+            },
+        })
+    }
+
+    /// Stop all order placement and amendments in a stock until resumed,
+    /// cancels still go through
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn halt_trading(&self, stock_id: i64) -> Result<(), AppError> {
+        let book = self.book(stock_id);
+        let mut book = book.lock().await;
+
+        self.db
+            .set_trading_halt(stock_id, true, book.halted_until)
+            .await?;
+        book.halted = true;
+
+        Ok(())
+    }
+
+    /// Lift an admin halt along with any circuit breaker cool-down
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn resume_trading(&self, stock_id: i64) -> Result<(), AppError> {
+        let book = self.book(stock_id);
+        let mut book = book.lock().await;
+
+        self.resume(stock_id, &mut book).await
+    }
+
+    /// Resume every stock whose circuit breaker cool-down is over by `now`
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn end_halts(&self, now: DateTime<Utc>) -> Result<(), AppError> {
+        for stock_id in self.db.get_ended_halts(now).await? {
+            let book = self.book(stock_id);
+            let mut book = book.lock().await;
+
+            if !book.halted {
+                self.resume(stock_id, &mut book).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clear a halt and place the bracket exits it held back
+    async fn resume(&self, stock_id: i64, book: &mut OrderBook) -> Result<(), AppError> {
+        self.db.set_trading_halt(stock_id, false, None).await?;
+        book.halted = false;
+        book.halted_until = None;
+
+        let brackets = self
+            .db
+            .get_unplaced_brackets()
+            .await?
+            .into_iter()
+            .filter_map(|(group_id, s)| (s == stock_id).then_some(group_id))
+            .collect();
+        self.settle(stock_id, book, vec![], brackets).await;
+
+        Ok(())
+    }
+
+    /// Set or, with `None`, turn off the circuit breaker of a stock
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn set_circuit_breaker(
+        &self,
+        stock_id: i64,
+        circuit_breaker: Option<CircuitBreaker>,
+    ) -> Result<(), AppError> {
+        let book = self.book(stock_id);
+        let mut book = book.lock().await;
+
+        self.db
+            .set_circuit_breaker(stock_id, circuit_breaker)
+            .await?;
+        book.circuit_breaker = circuit_breaker;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self), fields(service.name = "engine"))]
+    pub async fn cancel_order(&self, user_id: i64, stock_tx_id: i64) -> Result<(), AppError> {
+        // Whose orders can be cancelled is left to `DB::cancel_order`, which
+        // knows about `CANCEL_ANY_ORDER`
+        let stock_id = self.db.get_order_stock(None, stock_tx_id).await?;
+        let book = self.book(stock_id);
+        let mut book = book.lock().await;
+
+        self.db.cancel_order(user_id, stock_tx_id).await?;
+        book.remove(stock_tx_id);
+        book.remove_stop(stock_tx_id);
+
+        Ok(())
+    }
+}
+
+fn prices(fills: &[Fill]) -> Vec<i64> {
+    fills.iter().map(|f| f.price).collect()
+}
+
+/// Take orders cancelled in the database off the book, wherever they rest
+fn remove_orders(book: &mut OrderBook, order_ids: &[i64]) {
+    for order_id in order_ids {
+        book.remove(*order_id);
+        book.remove_stop(*order_id);
+    }
+}
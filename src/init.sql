@@ -1,14 +1,66 @@
+CREATE TABLE fee_schedules (
+    tier TEXT PRIMARY KEY,
+    -- Rates in basis points of the trade notional
+    maker_bps BIGINT NOT NULL,
+    taker_bps BIGINT NOT NULL,
+    -- Least charged per trade, if anything
+    min_fee BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO fee_schedules (tier, maker_bps, taker_bps) VALUES ('standard', 0, 0);
+
 CREATE TABLE users (
     user_id BIGSERIAL PRIMARY KEY,
     user_name TEXT NOT NULL UNIQUE,
     password TEXT NOT NULL,
-    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    fee_tier TEXT NOT NULL DEFAULT 'standard',
+    -- Can use the admin endpoints
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (fee_tier) REFERENCES fee_schedules(tier)
 );
 CREATE INDEX idx_users_user_name ON users(user_name);
 
 CREATE TABLE stocks (
     stock_id BIGSERIAL PRIMARY KEY,
     stock_name TEXT NOT NULL,
+    -- Sequence number of the latest print on the trade tape
+    last_trade_seq BIGINT NOT NULL DEFAULT 0,
+    trading_phase BIGINT NOT NULL DEFAULT 0,
+    tick_size BIGINT NOT NULL DEFAULT 1,
+    lot_size BIGINT NOT NULL DEFAULT 1,
+    max_order_quantity BIGINT,
+    -- Furthest a limit price may be from the last trade, in percent of it
+    price_collar_percent BIGINT,
+    -- Halted by an admin until resumed
+    halted BOOLEAN NOT NULL DEFAULT FALSE,
+    -- End of the cool-down after the circuit breaker tripped
+    halted_until TIMESTAMP,
+    -- Circuit breaker, off while NULL
+    breaker_move_percent BIGINT,
+    breaker_window_secs BIGINT,
+    breaker_halt_secs BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE auctions (
+    auction_id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL,
+    -- The call phase the auction ended, PreClose for the closing auction
+    phase BIGINT NOT NULL,
+    price BIGINT NOT NULL,
+    volume BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id)
+);
+CREATE INDEX idx_auctions_stock_id ON auctions(stock_id);
+
+CREATE TABLE order_groups (
+    group_id BIGSERIAL PRIMARY KEY,
+    group_type BIGINT NOT NULL,
+    -- Exits of a bracket, placed once its entry has filled
+    take_profit_price BIGINT,
+    stop_loss_price BIGINT,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
@@ -16,30 +68,102 @@ CREATE TABLE orders (
     order_id BIGSERIAL PRIMARY KEY,
     user_id BIGINT NOT NULL,
     stock_id BIGINT NOT NULL,
+    is_buy BOOLEAN NOT NULL,
+    order_type BIGINT NOT NULL,
     amount BIGINT NOT NULL,
     limit_price BIGINT,
     order_status BIGINT NOT NULL,
+    time_in_force BIGINT NOT NULL,
+    expires_at TIMESTAMP,
+    expiry_reason BIGINT,
+    trigger_price BIGINT,
+    triggered_at TIMESTAMP,
+    parent_order BIGINT,
+    self_trade_prevention BIGINT NOT NULL DEFAULT 0,
+    display_quantity BIGINT,
+    group_id BIGINT,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- Time priority on the book, reset when an amendment loses it
+    queued_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     FOREIGN KEY (user_id) REFERENCES users(user_id),
-    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id)
+    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id),
+    FOREIGN KEY (parent_order) REFERENCES orders(order_id),
+    FOREIGN KEY (group_id) REFERENCES order_groups(group_id)
 );
-CREATE INDEX idx_orders_user_id ON orders(user_id);
+-- A user's history is paged by time and filtered by stock
+CREATE INDEX idx_orders_user_id_created_at ON orders(user_id, created_at, order_id);
+CREATE INDEX idx_orders_user_id_stock_id ON orders(user_id, stock_id);
 CREATE INDEX idx_orders_stock_id ON orders(stock_id);
+CREATE INDEX idx_orders_group_id ON orders(group_id) WHERE group_id IS NOT NULL;
+CREATE INDEX idx_orders_expires_at ON orders(expires_at) WHERE expires_at IS NOT NULL;
+
+CREATE TABLE order_amendments (
+    amendment_id BIGSERIAL PRIMARY KEY,
+    order_id BIGINT NOT NULL,
+    previous_amount BIGINT NOT NULL,
+    previous_limit_price BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    limit_price BIGINT NOT NULL,
+    kept_priority BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (order_id) REFERENCES orders(order_id)
+);
+CREATE INDEX idx_order_amendments_order_id ON order_amendments(order_id);
 
 CREATE TABLE trades (
     trade_id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL,
+    -- Per stock tape sequence, NULL for setup transfers
+    seq BIGINT,
     sell_order BIGINT NOT NULL,
     buy_order BIGINT NOT NULL,
     amount BIGINT NOT NULL,
+    price BIGINT NOT NULL,
+    aggressor_is_buy BOOLEAN,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id),
     FOREIGN KEY (sell_order) REFERENCES orders(order_id),
     FOREIGN KEY (buy_order) REFERENCES orders(order_id)
 );
-CREATE INDEX idx_sell_order ON trades(sell_order);
-CREATE INDEX idx_buy_order ON trades(buy_order);
+-- Fills of an order in time order, for paging a user's history
+CREATE INDEX idx_sell_order ON trades(sell_order, created_at, trade_id);
+CREATE INDEX idx_buy_order ON trades(buy_order, created_at, trade_id);
+CREATE UNIQUE INDEX idx_trades_stock_seq ON trades(stock_id, seq);
 
+-- OHLCV bars per stock and interval, rolled up as trades are written
+CREATE TABLE candles (
+    stock_id BIGINT NOT NULL,
+    interval_secs BIGINT NOT NULL,
+    bucket TIMESTAMP NOT NULL,
+    open BIGINT NOT NULL,
+    high BIGINT NOT NULL,
+    low BIGINT NOT NULL,
+    close BIGINT NOT NULL,
+    volume BIGINT NOT NULL,
+    PRIMARY KEY (stock_id, interval_secs, bucket),
+    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id)
+);
+
+-- Fees charged on trades, numbered along with the trades since both are
+-- wallet entries
+CREATE TABLE fees (
+    fee_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
+    trade_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    order_id BIGINT NOT NULL,
+    is_maker BOOLEAN NOT NULL,
+    amount BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (trade_id) REFERENCES trades(trade_id),
+    FOREIGN KEY (user_id) REFERENCES users(user_id),
+    FOREIGN KEY (order_id) REFERENCES orders(order_id)
+);
+CREATE INDEX idx_fees_user_id ON fees(user_id);
+
+-- Deposits, withdrawals and adjustments are numbered along with the trades
+-- and fees, all being wallet transactions
 CREATE TABLE deposits (
-    deposit_id BIGSERIAL PRIMARY KEY,
+    deposit_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
     user_id BIGINT NOT NULL,
     amount BIGINT NOT NULL,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
@@ -47,5 +171,77 @@ CREATE TABLE deposits (
 );
 CREATE INDEX idx_deposits_user_id ON deposits(user_id);
 
-INSERT INTO users (user_name, password) VALUES
-('admin', '$argon2id$v=19$m=1024,t=1,p=1$HAZcjX8wBnPhvVhYBpXO5g$H009UoKExbLzSHbl5Ru6WEQ4djyRi5sU8fkfCwk8ulI');
+CREATE TABLE withdrawals (
+    withdrawal_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
+    user_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    status BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- When it was approved or rejected
+    decided_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+CREATE INDEX idx_withdrawals_user_id ON withdrawals(user_id);
+
+-- Corrections to a user's balance, positive to credit it
+CREATE TABLE adjustments (
+    adjustment_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
+    user_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+CREATE INDEX idx_adjustments_user_id ON adjustments(user_id);
+
+-- Double-entry cash ledger. Users each have a cash account, the exchange's
+-- own accounts have no user
+CREATE TABLE ledger_accounts (
+    account_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT UNIQUE,
+    account_type BIGINT NOT NULL,
+    -- Running total of the account's postings, only kept for users'
+    -- accounts. The exchange's accounts take part in most entries, so theirs
+    -- is summed from their postings rather than every entry queueing on
+    -- their row
+    balance BIGINT DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+CREATE UNIQUE INDEX idx_ledger_accounts_exchange ON ledger_accounts(account_type) WHERE user_id IS NULL;
+-- Funding, fee revenue and adjustment accounts
+INSERT INTO ledger_accounts (account_type, balance) VALUES (1, NULL), (2, NULL), (3, NULL);
+
+-- One entry per cash movement, pointing at what caused it
+CREATE TABLE journal_entries (
+    entry_id BIGSERIAL PRIMARY KEY,
+    entry_type BIGINT NOT NULL,
+    deposit_id BIGINT UNIQUE,
+    trade_id BIGINT UNIQUE,
+    fee_id BIGINT UNIQUE,
+    withdrawal_id BIGINT UNIQUE,
+    adjustment_id BIGINT UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (deposit_id) REFERENCES deposits(deposit_id),
+    FOREIGN KEY (trade_id) REFERENCES trades(trade_id),
+    FOREIGN KEY (fee_id) REFERENCES fees(fee_id),
+    FOREIGN KEY (withdrawal_id) REFERENCES withdrawals(withdrawal_id),
+    FOREIGN KEY (adjustment_id) REFERENCES adjustments(adjustment_id)
+);
+
+-- The postings of an entry sum to zero. A positive amount adds to the
+-- account's balance
+CREATE TABLE postings (
+    posting_id BIGSERIAL PRIMARY KEY,
+    entry_id BIGINT NOT NULL,
+    account_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    FOREIGN KEY (entry_id) REFERENCES journal_entries(entry_id),
+    FOREIGN KEY (account_id) REFERENCES ledger_accounts(account_id)
+);
+CREATE INDEX idx_postings_entry_id ON postings(entry_id);
+CREATE INDEX idx_postings_account_id ON postings(account_id);
+
+INSERT INTO users (user_name, password, is_admin) VALUES
+('admin', '$argon2id$v=19$m=1024,t=1,p=1$HAZcjX8wBnPhvVhYBpXO5g$H009UoKExbLzSHbl5Ru6WEQ4djyRi5sU8fkfCwk8ulI', TRUE);
+INSERT INTO ledger_accounts (user_id, account_type) SELECT user_id, 0 FROM users;
@@ -1,25 +1,36 @@
-use std::assert_matches::assert_matches;
+use std::{assert_matches, time::Duration};
 
 use axum::{
     body::Body,
     http::{self, Request, StatusCode},
     routing::RouterIntoService,
 };
+use chrono::{SecondsFormat, Utc};
 use http::request::Builder;
 use pretty_assertions::assert_eq;
 use serde::{Deserialize, Serialize, de};
 use tower::{Service, ServiceExt};
 
 use crate::{
-    admin::{AddMoneyRequest, AddStockToUserRequest, CreateStockRequest},
+    admin::{
+        AddMoneyRequest, AddStockToUserRequest, AdjustWalletRequest, CreateStockRequest,
+        SetCircuitBreakerRequest, SetFeeTierRequest, SetTradingPhaseRequest, TradingHaltRequest,
+        UpdateStockRequest, WithdrawMoneyRequest, WithdrawalDecisionRequest,
+    },
     db::DB,
-    order::{CancelStockTransactionRequest, PlaceStockOrderRequest},
+    engine::Engine,
+    order::{
+        CancelStockTransactionRequest, ModifyStockOrderRequest, OrderLeg, PlaceStockOrderRequest,
+    },
     router,
     telemetry::tracing_init,
     types::{
-        AppState, Balance, OrderStatus, OrderType, StockId, StockPortfolio, StockPortfolioVec,
-        StockPrice, StockPriceVec, StockTransaction, TokenResponse, TradeVec, WalletTransaction,
-        WalletVec,
+        AppState, AuctionIndicative, Balance, Candle, CandleVec, CircuitBreaker, EntryType,
+        ExpiryReason, FeeSchedule, MarketTradeVec, OrderAmendment, OrderBookDepth, OrderGroupType,
+        OrderStatus, OrderType, PortfolioSummary, PriceLevel, SelfTradePrevention, Side, StockId,
+        StockPortfolio, StockPortfolioVec, StockPrice, StockPriceVec, StockTransaction,
+        TimeInForce, TokenResponse, TradeVec, TradingPhase, TradingRules, WalletTransaction,
+        WalletVec, Withdrawal, WithdrawalStatus, WithdrawalVec,
     },
     user::{LoginRequest, RegisterRequest},
 };
@@ -77,6 +88,18 @@ async fn integration() {
     let vanguard_token = resp.token;
     assert_eq!((sc, vanguard_token.len() > 10), (StatusCode::OK, true));
 
+    // Admin Login
+    let (sc, resp) = app
+        .clone()
+        .login(LoginRequest {
+            user_name: String::from("admin"),
+            password: String::from("pass"),
+        })
+        .await
+        .unwrap();
+    let admin_token = resp.token;
+    assert_eq!((sc, admin_token.len() > 10), (StatusCode::OK, true));
+
     // Create Google Stock
     let (sc, resp) = app
         .clone()
@@ -84,6 +107,7 @@ async fn integration() {
             &vanguard_token,
             CreateStockRequest {
                 stock_name: String::from("Google"),
+                ..Default::default()
             },
         )
         .await
@@ -112,6 +136,7 @@ async fn integration() {
             &vanguard_token,
             CreateStockRequest {
                 stock_name: String::from("Apple"),
+                ..Default::default()
             },
         )
         .await
@@ -147,12 +172,22 @@ async fn integration() {
                 StockPortfolio {
                     stock_id: google_stock_id.clone(),
                     stock_name: String::from("Google"),
-                    quantity_owned: 550
+                    quantity_owned: 550,
+                    average_cost: None,
+                    cost_basis: 0,
+                    market_value: None,
+                    unrealized_pnl: None,
+                    realized_pnl: 0,
                 },
                 StockPortfolio {
                     stock_id: apple_stock_id.clone(),
                     stock_name: String::from("Apple"),
-                    quantity_owned: 350
+                    quantity_owned: 350,
+                    average_cost: None,
+                    cost_basis: 0,
+                    market_value: None,
+                    unrealized_pnl: None,
+                    realized_pnl: 0,
                 },
             ]
         )
@@ -169,6 +204,7 @@ async fn integration() {
                 order_type: OrderType::Limit,
                 quantity: 550,
                 price: Some(135),
+                ..Default::default()
             },
         )
         .await
@@ -186,6 +222,7 @@ async fn integration() {
                 order_type: OrderType::Limit,
                 quantity: 350,
                 price: Some(140),
+                ..Default::default()
             },
         )
         .await
@@ -268,12 +305,26 @@ async fn integration() {
                 StockPrice {
                     stock_id: google_stock_id.clone(),
                     stock_name: "Google".to_string(),
-                    current_price: 135
+                    current_price: 135,
+                    last_price: None,
+                    best_bid: None,
+                    best_ask: Some(135),
+                    previous_close: None,
+                    day_change: None,
+                    volume_24h: 0,
+                    trading_halted: false,
                 },
                 StockPrice {
                     stock_id: apple_stock_id.clone(),
                     stock_name: "Apple".to_string(),
-                    current_price: 140
+                    current_price: 140,
+                    last_price: None,
+                    best_bid: None,
+                    best_ask: Some(140),
+                    previous_close: None,
+                    day_change: None,
+                    volume_24h: 0,
+                    trading_halted: false,
                 }
             ]
         )
@@ -302,6 +353,7 @@ async fn integration() {
                 order_type: OrderType::Market,
                 quantity: 10,
                 price: None,
+                ..Default::default()
             },
         )
         .await
@@ -337,11 +389,22 @@ async fn integration() {
 
     assert_matches!(
         &resp.0[..],
-        [WalletTransaction {
-            is_debit: true,
-            amount: 1350,
-            ..
-        }]
+        [
+            WalletTransaction {
+                kind: EntryType::Deposit,
+                stock_tx_id: None,
+                is_debit: false,
+                amount: 10_000,
+                ..
+            },
+            WalletTransaction {
+                kind: EntryType::Trade,
+                stock_tx_id: Some(..),
+                is_debit: true,
+                amount: 1350,
+                ..
+            }
+        ]
     );
     assert_eq!(sc, StatusCode::OK);
 
@@ -358,7 +421,12 @@ async fn integration() {
             vec![StockPortfolio {
                 stock_id: google_stock_id.clone(),
                 stock_name: String::from("Google"),
-                quantity_owned: 10
+                quantity_owned: 10,
+                average_cost: Some(135),
+                cost_basis: 1350,
+                market_value: Some(1350),
+                unrealized_pnl: Some(0),
+                realized_pnl: 0,
             },]
         )
     );
@@ -425,6 +493,7 @@ async fn integration() {
     assert_matches!(
         &resp.0[..],
         [WalletTransaction {
+            kind: EntryType::Trade,
             is_debit: false,
             amount: 1350,
             ..
@@ -443,6 +512,7 @@ async fn integration() {
                 order_type: OrderType::Market,
                 quantity: 20,
                 price: None,
+                ..Default::default()
             },
         )
         .await
@@ -489,6 +559,13 @@ async fn integration() {
     assert_matches!(
         &resp.0[..],
         [
+            WalletTransaction {
+                kind: EntryType::Deposit,
+                stock_tx_id: None,
+                is_debit: false,
+                amount: 10_000,
+                ..
+            },
             WalletTransaction {
                 is_debit: true,
                 amount: 1350,
@@ -518,12 +595,22 @@ async fn integration() {
                 StockPortfolio {
                     stock_id: google_stock_id.clone(),
                     stock_name: String::from("Google"),
-                    quantity_owned: 10
+                    quantity_owned: 10,
+                    average_cost: Some(135),
+                    cost_basis: 1350,
+                    market_value: Some(1350),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
                 StockPortfolio {
                     stock_id: apple_stock_id.clone(),
                     stock_name: String::from("Apple"),
-                    quantity_owned: 20
+                    quantity_owned: 20,
+                    average_cost: Some(140),
+                    cost_basis: 2800,
+                    market_value: Some(2800),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
             ]
         )
@@ -540,6 +627,7 @@ async fn integration() {
                 order_type: OrderType::Limit,
                 quantity: 5,
                 price: Some(130),
+                ..Default::default()
             },
         )
         .await
@@ -598,6 +686,13 @@ async fn integration() {
     assert_matches!(
         &resp.0[..],
         [
+            WalletTransaction {
+                kind: EntryType::Deposit,
+                stock_tx_id: None,
+                is_debit: false,
+                amount: 10_000,
+                ..
+            },
             WalletTransaction {
                 is_debit: true,
                 amount: 1350,
@@ -628,11 +723,21 @@ async fn integration() {
                     stock_id: google_stock_id.clone(),
                     stock_name: String::from("Google"),
                     quantity_owned: 5,
+                    average_cost: Some(135),
+                    cost_basis: 1350,
+                    market_value: Some(1350),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
                 StockPortfolio {
                     stock_id: apple_stock_id.clone(),
                     stock_name: String::from("Apple"),
                     quantity_owned: 20,
+                    average_cost: Some(140),
+                    cost_basis: 2800,
+                    market_value: Some(2800),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
             ]
         )
@@ -648,12 +753,26 @@ async fn integration() {
                 StockPrice {
                     stock_id: google_stock_id.clone(),
                     stock_name: "Google".to_string(),
-                    current_price: 130
+                    current_price: 130,
+                    last_price: Some(135),
+                    best_bid: None,
+                    best_ask: Some(130),
+                    previous_close: None,
+                    day_change: None,
+                    volume_24h: 10,
+                    trading_halted: false,
                 },
                 StockPrice {
                     stock_id: apple_stock_id.clone(),
                     stock_name: "Apple".to_string(),
-                    current_price: 140
+                    current_price: 140,
+                    last_price: Some(140),
+                    best_bid: None,
+                    best_ask: Some(140),
+                    previous_close: None,
+                    day_change: None,
+                    volume_24h: 20,
+                    trading_halted: false,
                 }
             ]
         )
@@ -670,6 +789,7 @@ async fn integration() {
                 order_type: OrderType::Market,
                 quantity: 2,
                 price: None,
+                ..Default::default()
             },
         )
         .await
@@ -782,7 +902,7 @@ async fn integration() {
     assert_eq!((sc, resp.balance), (StatusCode::OK, 3890));
     // TODO: +400 off from pdf due to incorrect pricing
 
-    // Vanguard buy 5 Google (and fail)
+    // Vanguard buy 5 Google (only User1's 3 are available, rest is cancelled)
     let sc = app
         .clone()
         .place_stock_order(
@@ -793,13 +913,14 @@ async fn integration() {
                 order_type: OrderType::Market,
                 quantity: 5,
                 price: None,
+                ..Default::default()
             },
         )
         .await
         .unwrap();
     assert_eq!(sc, StatusCode::CREATED);
 
-    // User1 cancel Google sell order
+    // User1 cancel Google sell order (already completed)
     let sc = app
         .clone()
         .cancel_stock_order(
@@ -809,8 +930,8 @@ async fn integration() {
             },
         )
         .await
-        .unwrap();
-    assert_eq!(sc, StatusCode::OK);
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
 
     // Vanguard cancel Google sell order
     let sc = app
@@ -901,11 +1022,34 @@ async fn integration() {
                 quantity: 2,
                 ..
             },
+            StockTransaction {
+                //stock_id: google_stock_id,
+                parent_stock_tx_id: None,
+                wallet_tx_id: None,
+                order_status: OrderStatus::PartiallyComplete,
+                order_type: OrderType::Market,
+                is_buy: true,
+                stock_price: 130,
+                quantity: 5,
+                ..
+            },
+            StockTransaction {
+                //stock_id: google_stock_id,
+                parent_stock_tx_id: Some(..),
+                wallet_tx_id: Some(..),
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Market,
+                is_buy: true,
+                stock_price: 130,
+                quantity: 3,
+                ..
+            },
         ]
     );
     assert_eq!(sc, StatusCode::OK);
 
-    // Get Vanguard Stock Portfolio
+    // Get Vanguard Stock Portfolio. Its shares were seeded at no cost, so
+    // only the 5 Google it bought count towards profit
     let (sc, resp) = app
         .clone()
         .get_stock_portfolio(&vanguard_token)
@@ -919,12 +1063,22 @@ async fn integration() {
                 StockPortfolio {
                     stock_id: google_stock_id.clone(),
                     stock_name: String::from("Google"),
-                    quantity_owned: 542
+                    quantity_owned: 545,
+                    average_cost: Some(650 / 5),
+                    cost_basis: 650,
+                    market_value: Some(70850),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
                 StockPortfolio {
                     stock_id: apple_stock_id.clone(),
                     stock_name: String::from("Apple"),
-                    quantity_owned: 330
+                    quantity_owned: 330,
+                    average_cost: None,
+                    cost_basis: 0,
+                    market_value: Some(46200),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
             ]
         )
@@ -960,7 +1114,7 @@ async fn integration() {
             },
             StockTransaction {
                 parent_stock_tx_id: None,
-                order_status: OrderStatus::Cancelled,
+                order_status: OrderStatus::Completed,
                 order_type: OrderType::Limit,
                 is_buy: false,
                 stock_price: 130,
@@ -977,6 +1131,16 @@ async fn integration() {
                 quantity: 2,
                 ..
             },
+            StockTransaction {
+                parent_stock_tx_id: Some(..),
+                wallet_tx_id: Some(..),
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Limit,
+                is_buy: false,
+                stock_price: 130,
+                quantity: 3,
+                ..
+            },
         ]
     );
     assert_eq!(sc, StatusCode::OK);
@@ -991,12 +1155,22 @@ async fn integration() {
                 StockPortfolio {
                     stock_id: google_stock_id.clone(),
                     stock_name: String::from("Google"),
-                    quantity_owned: 8,
+                    quantity_owned: 5,
+                    average_cost: Some(135),
+                    cost_basis: 675,
+                    market_value: Some(650),
+                    unrealized_pnl: Some(-25),
+                    realized_pnl: -25,
                 },
                 StockPortfolio {
                     stock_id: apple_stock_id.clone(),
                     stock_name: String::from("Apple"),
                     quantity_owned: 20,
+                    average_cost: Some(140),
+                    cost_basis: 2800,
+                    market_value: Some(2800),
+                    unrealized_pnl: Some(0),
+                    realized_pnl: 0,
                 },
             ]
         )
@@ -1045,6 +1219,7 @@ async fn integration() {
                 order_type: OrderType::Market,
                 quantity: 20,
                 price: Some(80),
+                ..Default::default()
             },
         )
         .await
@@ -1070,108 +1245,3191 @@ async fn integration() {
                 order_type: OrderType::Market,
                 quantity: 20,
                 price: None,
+                ..Default::default()
             },
         )
         .await
         .unwrap_err();
     assert_eq!(sc, StatusCode::UNAUTHORIZED);
-}
 
-#[derive(Serialize, Deserialize)]
-struct ApiResponseWrapper<T> {
-    success: bool,
-    data: T,
-}
+    // User1 sell more Apple than they own
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: apple_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 21,
+                price: Some(150),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
 
-#[derive(Clone)]
-struct App {
-    app: RouterIntoService<Body>,
-}
+    // User1 sell 15 Apple (locks the shares)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: apple_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 15,
+                price: Some(150),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
 
-impl App {
-    async fn init() -> Self {
-        let state = AppState {
-            db: DB::init().await.unwrap(),
-        };
+    // User1 market sell more Apple than is left unlocked
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: apple_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Market,
+                quantity: 6,
+                price: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
 
-        App {
-            app: router(state).await.into_service(),
-        }
-    }
+    // Vanguard cancel User1's Apple sell order (not theirs)
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    let user1_apple_ask = resp.0.last().unwrap().stock_tx_id.clone();
+    let sc = app
+        .clone()
+        .cancel_stock_order(
+            &vanguard_token,
+            CancelStockTransactionRequest {
+                stock_tx_id: user1_apple_ask.clone(),
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
 
-    async fn request<B: Serialize, R: for<'a> de::Deserialize<'a>>(
-        mut self,
-        token: &String,
-        request: Builder,
-        payload: Option<B>,
-    ) -> Result<(StatusCode, R), StatusCode> {
-        let request = request.header("token", token);
-        let request = if let Some(ref p) = payload {
-            request
-                .header("Content-Type", "application/json")
-                .body(Body::from(serde_json::to_string(&p).unwrap()))
-        } else {
-            request.body(Body::empty())
-        }
+    // User1 get stock transactions (Apple sell order still open)
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
         .unwrap();
+    assert_matches!(
+        resp.0.last(),
+        Some(StockTransaction {
+            stock_tx_id,
+            order_status: OrderStatus::InProgress,
+            quantity: 15,
+            ..
+        }) if *stock_tx_id == user1_apple_ask
+    );
+    assert_eq!(sc, StatusCode::OK);
 
-        let response = ServiceExt::<Request<Body>>::ready(&mut self.app)
-            .await
-            .unwrap()
-            .call(request)
-            .await
-            .unwrap();
+    // Create Tesla Stock
+    let (sc, resp) = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Tesla"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let tesla_stock_id = resp.stock_id;
+    assert_eq!(sc, 200);
 
-        let (_parts, rawbody) = response.into_parts();
-        let bytes = axum::body::to_bytes(rawbody, usize::MAX).await.unwrap();
-        let obj: ApiResponseWrapper<R> =
-            serde_json::from_slice(&bytes).map_err(|_| _parts.status)?;
+    // Add 100 Tesla Stock to Vanguard
+    let sc = app
+        .clone()
+        .add_stock_to_user(
+            &vanguard_token,
+            AddStockToUserRequest {
+                stock_id: tesla_stock_id.clone(),
+                quantity: 100,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, 200);
 
-        Ok((_parts.status, obj.data))
-    }
+    // User1 limit buy 10 Tesla (rests on the bid)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: tesla_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 10,
+                price: Some(200),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
 
-    async fn register(self, payload: RegisterRequest) -> Result<StatusCode, StatusCode> {
-        let (sc, _resp) = self
-            .request::<_, Option<i64>>(
-                &"".to_string(),
-                Request::builder()
-                    .uri("/authentication/register")
-                    .method("POST"),
-                Some(payload),
-            )
-            .await?;
+    // Vanguard market sell 4 Tesla (hits User1's bid)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: tesla_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Market,
+                quantity: 4,
+                price: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
 
-        Ok(sc)
-    }
+    // Vanguard limit sell 10 Tesla below the bid (6 fill at 200, 4 rest)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: tesla_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 10,
+                price: Some(190),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
 
-    async fn login(self, payload: LoginRequest) -> Result<(StatusCode, TokenResponse), StatusCode> {
-        let resp = self
-            .request::<_, TokenResponse>(
-                &"".to_string(),
-                Request::builder()
-                    .uri("/authentication/login")
-                    .method("POST"),
-                Some(payload),
-            )
+    // User1 get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+
+    assert_matches!(
+        &resp.0[resp.0.len() - 3..],
+        [
+            StockTransaction {
+                parent_stock_tx_id: None,
+                wallet_tx_id: None,
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Limit,
+                is_buy: true,
+                stock_price: 200,
+                quantity: 10,
+                ..
+            },
+            StockTransaction {
+                parent_stock_tx_id: Some(..),
+                wallet_tx_id: Some(..),
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Limit,
+                is_buy: true,
+                stock_price: 200,
+                quantity: 4,
+                ..
+            },
+            StockTransaction {
+                parent_stock_tx_id: Some(..),
+                wallet_tx_id: Some(..),
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Limit,
+                is_buy: true,
+                stock_price: 200,
+                quantity: 6,
+                ..
+            },
+        ]
+    );
+    assert_eq!(sc, StatusCode::OK);
+
+    // Vanguard get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+
+    assert_matches!(
+        &resp.0[resp.0.len() - 3..],
+        [
+            StockTransaction {
+                parent_stock_tx_id: None,
+                wallet_tx_id: Some(..),
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Market,
+                is_buy: false,
+                stock_price: 200,
+                quantity: 4,
+                ..
+            },
+            StockTransaction {
+                parent_stock_tx_id: None,
+                wallet_tx_id: None,
+                order_status: OrderStatus::PartiallyComplete,
+                order_type: OrderType::Limit,
+                is_buy: false,
+                stock_price: 190,
+                quantity: 10,
+                ..
+            },
+            StockTransaction {
+                parent_stock_tx_id: Some(..),
+                wallet_tx_id: Some(..),
+                order_status: OrderStatus::Completed,
+                order_type: OrderType::Limit,
+                is_buy: false,
+                stock_price: 200,
+                quantity: 6,
+                ..
+            },
+        ]
+    );
+    assert_eq!(sc, StatusCode::OK);
+
+    // User1 Stock Portfolio
+    let (sc, resp) = app.clone().get_stock_portfolio(&user1_token).await.unwrap();
+    assert_eq!(
+        (sc, &resp.0[2]),
+        (
+            StatusCode::OK,
+            &StockPortfolio {
+                stock_id: tesla_stock_id.clone(),
+                stock_name: String::from("Tesla"),
+                quantity_owned: 10,
+                average_cost: Some(200),
+                cost_basis: 2000,
+                market_value: Some(2000),
+                unrealized_pnl: Some(0),
+                realized_pnl: 0,
+            }
+        )
+    );
+
+    // User1 get wallet balance
+    let (sc, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (sc, resp.balance, resp.available, resp.held),
+        (StatusCode::OK, 4500, 4500, 0)
+    );
+
+    // User1 limit buy 5 Tesla under the ask (holds 750)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: tesla_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 5,
+                price: Some(150),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 get wallet balance
+    let (sc, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (sc, resp.balance, resp.available, resp.held),
+        (StatusCode::OK, 4500, 3750, 750)
+    );
+
+    // User1 limit buy more Tesla than the available balance covers
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: tesla_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 25,
+                price: Some(175),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // User1 market buy the 4 Tesla left on Vanguard's ask (4 x 190)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: tesla_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Market,
+                quantity: 4,
+                price: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 cancel the resting Tesla buy (releases the hold)
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    let user1_tesla_bid = resp
+        .0
+        .iter()
+        .find(|tx| tx.is_buy && tx.order_status == OrderStatus::InProgress)
+        .unwrap()
+        .stock_tx_id
+        .clone();
+    let sc = app
+        .clone()
+        .cancel_stock_order(
+            &user1_token,
+            CancelStockTransactionRequest {
+                stock_tx_id: user1_tesla_bid,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // User1 get wallet balance
+    let (sc, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (sc, resp.balance, resp.available, resp.held),
+        (StatusCode::OK, 3740, 3740, 0)
+    );
+    // Create Microsoft Stock
+    let (sc, resp) = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Microsoft"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let microsoft_stock_id = resp.stock_id;
+    assert_eq!(sc, 200);
+
+    // Add 20 Microsoft Stock to Vanguard
+    let sc = app
+        .clone()
+        .add_stock_to_user(
+            &vanguard_token,
+            AddStockToUserRequest {
+                stock_id: microsoft_stock_id.clone(),
+                quantity: 20,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // Vanguard sell 10 Microsoft at 300 (GTC by default)
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 10,
+                price: Some(300),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Market orders can't rest
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Market,
+                quantity: 1,
+                price: None,
+                time_in_force: Some(TimeInForce::GoodTilCancelled),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // GTD needs an expiry
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 5,
+                price: Some(310),
+                time_in_force: Some(TimeInForce::GoodTilDate),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // User1 FOK buy 15 Microsoft, only 10 on offer so nothing trades
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 15,
+                price: Some(300),
+                time_in_force: Some(TimeInForce::FillOrKill),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 IOC buy 12 Microsoft, 10 fill and the rest expires
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 12,
+                price: Some(300),
+                time_in_force: Some(TimeInForce::ImmediateOrCancel),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let microsoft_orders = resp
+        .0
+        .iter()
+        .filter(|tx| tx.stock_id == microsoft_stock_id && tx.parent_stock_tx_id.is_none())
+        .collect::<Vec<_>>();
+    assert_matches!(
+        &microsoft_orders[..],
+        [
+            StockTransaction {
+                order_status: OrderStatus::Expired,
+                time_in_force: TimeInForce::FillOrKill,
+                expiry_reason: Some(ExpiryReason::NotFullyFillable),
+                quantity: 15,
+                ..
+            },
+            StockTransaction {
+                order_status: OrderStatus::Expired,
+                time_in_force: TimeInForce::ImmediateOrCancel,
+                expiry_reason: Some(ExpiryReason::NotFilledImmediately),
+                quantity: 12,
+                ..
+            },
+        ]
+    );
+
+    // User1 get wallet balance, nothing held for the expired remainders
+    let (sc, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (sc, resp.balance, resp.available, resp.held),
+        (StatusCode::OK, 740, 740, 0)
+    );
+
+    // Vanguard sell 5 Microsoft at 310 good for one second
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 5,
+                price: Some(310),
+                time_in_force: Some(TimeInForce::GoodTilDate),
+                expires_at: Some(Utc::now() + Duration::from_secs(1)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Give the sweeper time to expire it
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    // Vanguard get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_matches!(
+        resp.0.last(),
+        Some(StockTransaction {
+            order_status: OrderStatus::Expired,
+            time_in_force: TimeInForce::GoodTilDate,
+            expiry_reason: Some(ExpiryReason::ExpiryTimeReached),
+            quantity: 5,
+            ..
+        })
+    );
+
+    // User1 can't buy the expired shares
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Market,
+                quantity: 1,
+                price: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_matches!(
+        resp.0.last(),
+        Some(StockTransaction {
+            order_status: OrderStatus::Cancelled,
+            expiry_reason: Some(ExpiryReason::NotFilledImmediately),
+            ..
+        })
+    );
+    // A stop limit needs a trigger
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::StopLimit,
+                quantity: 1,
+                price: Some(320),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // User1 protective stop sell 4 Microsoft at 290
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Stop,
+                quantity: 4,
+                trigger_price: Some(290),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Vanguard bid 10 Microsoft at 285, under the trigger but nothing has traded
+    // yet
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 10,
+                price: Some(285),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 get stock transactions, the stop is still dormant
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_matches!(
+        resp.0.last(),
+        Some(StockTransaction {
+            order_status: OrderStatus::InProgress,
+            order_type: OrderType::Stop,
+            trigger_price: Some(290),
+            triggered_at: None,
+            quantity: 4,
+            ..
+        })
+    );
+    let user1_stop = resp.0.last().unwrap().stock_tx_id.clone();
+
+    // User1 market sell 1 Microsoft, printing at 285 and triggering the stop
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Market,
+                quantity: 1,
+                price: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_matches!(
+        resp.0.iter().find(|tx| tx.stock_tx_id == user1_stop),
+        Some(StockTransaction {
+            order_status: OrderStatus::Completed,
+            order_type: OrderType::Stop,
+            trigger_price: Some(290),
+            triggered_at: Some(_),
+            ..
+        })
+    );
+    assert_matches!(
+        resp.0
+            .iter()
+            .find(|tx| tx.parent_stock_tx_id.as_ref() == Some(&user1_stop)),
+        Some(StockTransaction {
+            order_status: OrderStatus::Completed,
+            order_type: OrderType::Market,
+            is_buy: false,
+            stock_price: 285,
+            quantity: 4,
+            trigger_price: None,
+            ..
+        })
+    );
+
+    // User1 get wallet balance (740 + 5 x 285)
+    let (sc, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (sc, resp.balance, resp.available, resp.held),
+        (StatusCode::OK, 2165, 2165, 0)
+    );
+
+    // User1 stop limit buy that never triggers can be cancelled
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::StopLimit,
+                quantity: 2,
+                price: Some(330),
+                trigger_price: Some(320),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    let sc = app
+        .clone()
+        .cancel_stock_order(
+            &user1_token,
+            CancelStockTransactionRequest {
+                stock_tx_id: resp.0.last().unwrap().stock_tx_id.clone(),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    // Vanguard bid 3 more Microsoft at 280
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 3,
+                price: Some(280),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    let vanguard_microsoft_bids = resp
+        .0
+        .iter()
+        .filter(|tx| {
+            tx.stock_id == microsoft_stock_id && tx.is_buy && tx.parent_stock_tx_id.is_none()
+        })
+        .map(|tx| tx.stock_tx_id.clone())
+        .collect::<Vec<_>>();
+    let [bid_285, bid_280] = &vanguard_microsoft_bids[..] else {
+        panic!("expected two Microsoft bids, got {vanguard_microsoft_bids:?}");
+    };
+
+    // Vanguard shrink the 285 bid from 10 to 7 (5 filled, keeps its place)
+    let sc = app
+        .clone()
+        .modify_stock_order(
+            &vanguard_token,
+            ModifyStockOrderRequest {
+                stock_tx_id: bid_285.clone(),
+                quantity: Some(7),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // Vanguard reprice the 280 bid to 285, behind the other bid
+    let sc = app
+        .clone()
+        .modify_stock_order(
+            &vanguard_token,
+            ModifyStockOrderRequest {
+                stock_tx_id: bid_280.clone(),
+                price: Some(285),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // User1 can't amend Vanguard's order
+    let sc = app
+        .clone()
+        .modify_stock_order(
+            &user1_token,
+            ModifyStockOrderRequest {
+                stock_tx_id: bid_280.clone(),
+                quantity: Some(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Vanguard can't shrink an order below what already filled
+    let sc = app
+        .clone()
+        .modify_stock_order(
+            &vanguard_token,
+            ModifyStockOrderRequest {
+                stock_tx_id: bid_285.clone(),
+                quantity: Some(5),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // User1 market sell 3 Microsoft, filling the older bid first
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Market,
+                quantity: 3,
+                price: None,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Vanguard get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let order = |id: &String| {
+        resp.0
+            .iter()
+            .find(|tx| &tx.stock_tx_id == id && tx.parent_stock_tx_id.is_none())
+    };
+    assert_matches!(
+        order(bid_285),
+        Some(StockTransaction {
+            order_status: OrderStatus::Completed,
+            stock_price: 285,
+            quantity: 7,
+            amendments,
+            ..
+        }) if matches!(amendments[..], [OrderAmendment {
+            previous_quantity: 10,
+            quantity: 7,
+            kept_priority: true,
+            ..
+        }])
+    );
+    assert_matches!(
+        order(bid_280),
+        Some(StockTransaction {
+            order_status: OrderStatus::PartiallyComplete,
+            stock_price: 285,
+            quantity: 3,
+            amendments,
+            ..
+        }) if matches!(amendments[..], [OrderAmendment {
+            previous_price: 280,
+            price: 285,
+            kept_priority: false,
+            ..
+        }])
+    );
+    // User1 offer the last 2 Microsoft at 300 in two orders
+    for _ in 0..2 {
+        let sc = app
+            .clone()
+            .place_stock_order(
+                &user1_token,
+                PlaceStockOrderRequest {
+                    stock_id: microsoft_stock_id.clone(),
+                    is_buy: false,
+                    order_type: OrderType::Limit,
+                    quantity: 1,
+                    price: Some(300),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::CREATED);
+    }
+
+    // Microsoft order book
+    let (sc, resp) = app
+        .clone()
+        .get_order_book(&user1_token, &microsoft_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        (sc, resp.bids, resp.asks),
+        (
+            StatusCode::OK,
+            vec![PriceLevel {
+                price: 285,
+                quantity: 2,
+                order_count: 1
+            }],
+            vec![PriceLevel {
+                price: 300,
+                quantity: 2,
+                order_count: 2
+            }]
+        )
+    );
+
+    // Google order book, limited to the best level per side
+    let (sc, resp) = app
+        .clone()
+        .get_order_book(&user1_token, &google_stock_id, Some(1))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert!(resp.bids.len() <= 1 && resp.asks.len() <= 1);
+
+    // Bad depth and unknown stock
+    let sc = app
+        .clone()
+        .get_order_book(&user1_token, &microsoft_stock_id, Some(0))
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    let sc = app
+        .clone()
+        .get_order_book(&user1_token, &String::from("999999"), None)
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    // Microsoft trade tape
+    let (sc, resp) = app
+        .clone()
+        .get_market_trades(&user1_token, &microsoft_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        resp.0
+            .iter()
+            .map(|t| (t.seq, t.price, t.quantity, t.aggressor))
+            .collect::<Vec<_>>(),
+        vec![
+            (1, 300, 10, Some(Side::Buy)),
+            (2, 285, 1, Some(Side::Sell)),
+            (3, 285, 4, Some(Side::Sell)),
+            (4, 285, 2, Some(Side::Sell)),
+            (5, 285, 1, Some(Side::Sell)),
+        ]
+    );
+
+    // Resume after the third print
+    let (sc, resp) = app
+        .clone()
+        .get_market_trades(&user1_token, &microsoft_stock_id, Some(3))
+        .await
+        .unwrap();
+    assert_eq!(
+        (sc, resp.0.iter().map(|t| t.seq).collect::<Vec<_>>()),
+        (StatusCode::OK, vec![4, 5])
+    );
+    // Stock prices list every stock, with quotes and the last trade
+    let (sc, resp) = app.clone().get_stock_prices(&user1_token).await.unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(resp.0.len(), 4);
+    assert_eq!(
+        resp.0.iter().find(|p| p.stock_id == microsoft_stock_id),
+        Some(&StockPrice {
+            stock_id: microsoft_stock_id.clone(),
+            stock_name: "Microsoft".to_string(),
+            current_price: 300,
+            last_price: Some(285),
+            best_bid: Some(285),
+            best_ask: Some(300),
+            previous_close: None,
+            day_change: None,
+            volume_24h: 18,
+            trading_halted: false,
+        })
+    );
+    // Microsoft daily candle
+    let (sc, resp) = app
+        .clone()
+        .get_candles(
+            &user1_token,
+            &format!("stock_id={microsoft_stock_id}&interval=1d"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_matches!(
+        &resp.0[..],
+        [Candle {
+            open: 300,
+            high: 300,
+            low: 285,
+            close: 285,
+            volume: 18,
+            ..
+        }]
+    );
+
+    // Minute candles add up to the same volume
+    let (_, resp) = app
+        .clone()
+        .get_candles(
+            &user1_token,
+            &format!("stock_id={microsoft_stock_id}&interval=1m"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.0.iter().map(|c| c.volume).sum::<i64>(), 18);
+
+    // Nothing before the first trade
+    let (_, resp) = app
+        .clone()
+        .get_candles(
+            &user1_token,
+            &format!("stock_id={microsoft_stock_id}&interval=1h&to=2000-01-01T00:00:00Z"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.0, vec![]);
+
+    // User1 bid 1 Microsoft at 300 against their own asks, cancelled on
+    // arrival under the default self-trade prevention
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 1,
+                price: Some(300),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 bid again, cancelling their resting asks instead
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 1,
+                price: Some(300),
+                self_trade_prevention: SelfTradePrevention::CancelOldest,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // The asks are gone and the second bid rests at the top of the book
+    let (sc, resp) = app
+        .clone()
+        .get_order_book(&user1_token, &microsoft_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!((sc, resp.asks), (StatusCode::OK, vec![]));
+    assert_eq!(
+        resp.bids.first(),
+        Some(&PriceLevel {
+            price: 300,
+            quantity: 1,
+            order_count: 1
+        })
+    );
+
+    // User1 get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let self_trades = resp
+        .0
+        .iter()
+        .filter(|tx| tx.expiry_reason == Some(ExpiryReason::SelfTradePrevented))
+        .collect::<Vec<_>>();
+    assert_matches!(
+        &self_trades[..],
+        [
+            StockTransaction {
+                order_status: OrderStatus::Cancelled,
+                is_buy: false,
+                ..
+            },
+            StockTransaction {
+                order_status: OrderStatus::Cancelled,
+                is_buy: false,
+                ..
+            },
+            StockTransaction {
+                order_status: OrderStatus::Cancelled,
+                is_buy: true,
+                self_trade_prevention: SelfTradePrevention::CancelNewest,
+                ..
+            },
+        ]
+    );
+    let resting_bid = resp
+        .0
+        .iter()
+        .find(|tx| tx.stock_id == microsoft_stock_id && tx.order_status == OrderStatus::InProgress)
+        .unwrap();
+    assert_eq!(
+        (resting_bid.is_buy, resting_bid.self_trade_prevention),
+        (true, SelfTradePrevention::CancelOldest)
+    );
+
+    // Iceberg slices must fit in the order
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 6,
+                price: Some(305),
+                display_quantity: Some(7),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Vanguard sell 6 Microsoft at 305, showing 2 at a time
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 6,
+                price: Some(305),
+                display_quantity: Some(2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Only the first slice is on the book
+    let (sc, resp) = app
+        .clone()
+        .get_order_book(&user1_token, &microsoft_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        (sc, resp.asks),
+        (
+            StatusCode::OK,
+            vec![PriceLevel {
+                price: 305,
+                quantity: 2,
+                order_count: 1
+            }]
+        )
+    );
+
+    // User1 IOC buy 3 Microsoft, taking a slice and part of the next
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 3,
+                price: Some(305),
+                time_in_force: Some(TimeInForce::ImmediateOrCancel),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    let (_, resp) = app
+        .clone()
+        .get_order_book(&user1_token, &microsoft_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.asks,
+        vec![PriceLevel {
+            price: 305,
+            quantity: 1,
+            order_count: 1
+        }]
+    );
+
+    // Both fills roll up to the iceberg in Vanguard's history
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let iceberg = resp
+        .0
+        .iter()
+        .find(|tx| tx.display_quantity.is_some())
+        .unwrap();
+    assert_matches!(
+        iceberg,
+        StockTransaction {
+            order_status: OrderStatus::PartiallyComplete,
+            quantity: 6,
+            display_quantity: Some(2),
+            ..
+        }
+    );
+    assert_eq!(
+        resp.0
+            .iter()
+            .filter(|tx| tx.parent_stock_tx_id.as_ref() == Some(&iceberg.stock_tx_id))
+            .map(|tx| (tx.stock_price, tx.quantity))
+            .collect::<Vec<_>>(),
+        vec![(305, 2), (305, 1)]
+    );
+
+    // User1 offer their 5 Microsoft at 320 with a stop at 290 to get out
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 5,
+                price: Some(320),
+                one_cancels_other: Some(OrderLeg {
+                    order_type: OrderType::Stop,
+                    price: None,
+                    trigger_price: Some(290),
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Vanguard buy 2 Microsoft at 320, clearing their own iceberg out of
+    // the way, which partly fills the limit leg and shrinks the stop leg to
+    // the 3 left
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 2,
+                price: Some(320),
+                time_in_force: Some(TimeInForce::ImmediateOrCancel),
+                self_trade_prevention: SelfTradePrevention::CancelOldest,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 get stock transactions
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let oco_legs = resp
+        .0
+        .iter()
+        .filter(|tx| tx.order_group == Some(OrderGroupType::OneCancelsOther))
+        .collect::<Vec<_>>();
+    let [limit_leg, stop_leg] = &oco_legs[..] else {
+        panic!("expected two OCO legs, got {oco_legs:?}");
+    };
+    assert_matches!(
+        limit_leg,
+        StockTransaction {
+            order_status: OrderStatus::PartiallyComplete,
+            order_type: OrderType::Limit,
+            parent_stock_tx_id: None,
+            ..
+        }
+    );
+    assert_matches!(
+        stop_leg,
+        StockTransaction {
+            order_status: OrderStatus::InProgress,
+            order_type: OrderType::Stop,
+            quantity: 3,
+            trigger_price: Some(290),
+            ..
+        }
+    );
+    assert_eq!(
+        stop_leg.parent_stock_tx_id,
+        Some(limit_leg.stock_tx_id.clone())
+    );
+
+    // A bracket's take-profit has to be above a long entry
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 2,
+                price: Some(320),
+                take_profit: Some(310),
+                stop_loss: Some(250),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Vanguard buy 2 Microsoft at 320 with exits at 340 and 250, filled
+    // straight away by what's left of User1's limit leg
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 2,
+                price: Some(320),
+                take_profit: Some(340),
+                stop_loss: Some(250),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Both exits are live, pointing back at the entry
+    let (sc, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let bracket = resp
+        .0
+        .iter()
+        .filter(|tx| tx.order_group == Some(OrderGroupType::Bracket))
+        .collect::<Vec<_>>();
+    let [entry, take_profit, stop_loss] = &bracket[..] else {
+        panic!("expected a bracket entry and two exits, got {bracket:?}");
+    };
+    assert_matches!(
+        entry,
+        StockTransaction {
+            order_status: OrderStatus::Completed,
+            is_buy: true,
+            parent_stock_tx_id: None,
+            ..
+        }
+    );
+    assert_matches!(
+        stop_loss,
+        StockTransaction {
+            order_status: OrderStatus::InProgress,
+            order_type: OrderType::Stop,
+            is_buy: false,
+            quantity: 2,
+            trigger_price: Some(250),
+            ..
+        }
+    );
+    assert_matches!(
+        take_profit,
+        StockTransaction {
+            order_status: OrderStatus::InProgress,
+            order_type: OrderType::Limit,
+            is_buy: false,
+            stock_price: 340,
+            quantity: 2,
+            ..
+        }
+    );
+    assert_eq!(
+        (
+            &stop_loss.parent_stock_tx_id,
+            &take_profit.parent_stock_tx_id
+        ),
+        (
+            &Some(entry.stock_tx_id.clone()),
+            &Some(entry.stock_tx_id.clone())
+        )
+    );
+    let (stop_loss_id, take_profit_id) = (
+        stop_loss.stock_tx_id.clone(),
+        take_profit.stock_tx_id.clone(),
+    );
+
+    // User1 buy 2 Microsoft at 340, cancelling what's left of their own
+    // limit leg and taking out the take-profit
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 2,
+                price: Some(340),
+                time_in_force: Some(TimeInForce::ImmediateOrCancel),
+                self_trade_prevention: SelfTradePrevention::CancelOldest,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    let status = |id: &String| {
+        resp.0
+            .iter()
+            .find(|tx| &tx.stock_tx_id == id && tx.order_group.is_some())
+            .map(|tx| (tx.order_status, tx.expiry_reason))
+    };
+    assert_eq!(
+        (status(&take_profit_id), status(&stop_loss_id)),
+        (
+            Some((OrderStatus::Completed, None)),
+            Some((OrderStatus::Cancelled, Some(ExpiryReason::OtherLegExecuted)))
+        )
+    );
+
+    // Vanguard bid 1 Microsoft at 330
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &vanguard_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 1,
+                price: Some(330),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 offer 3 Microsoft at 330 with a stop at 280, partly filled on
+    // arrival so the stop leg only covers the 2 left
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: microsoft_stock_id.clone(),
+                is_buy: false,
+                order_type: OrderType::Limit,
+                quantity: 3,
+                price: Some(330),
+                one_cancels_other: Some(OrderLeg {
+                    order_type: OrderType::Stop,
+                    price: None,
+                    trigger_price: Some(280),
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    let limit_leg = resp
+        .0
+        .iter()
+        .find(|tx| {
+            tx.order_group == Some(OrderGroupType::OneCancelsOther)
+                && tx.order_type == OrderType::Limit
+                && tx.stock_price == 330
+        })
+        .unwrap();
+    assert_matches!(
+        limit_leg,
+        StockTransaction {
+            order_status: OrderStatus::PartiallyComplete,
+            quantity: 3,
+            ..
+        }
+    );
+    let stop_leg = resp
+        .0
+        .iter()
+        .find(|tx| tx.trigger_price == Some(280))
+        .unwrap();
+    assert_matches!(
+        stop_leg,
+        StockTransaction {
+            order_status: OrderStatus::InProgress,
+            order_type: OrderType::Stop,
+            quantity: 2,
+            ..
+        }
+    );
+    assert_eq!(
+        stop_leg.parent_stock_tx_id,
+        Some(limit_leg.stock_tx_id.clone())
+    );
+
+    // Nvidia to run through a trading session
+    let (sc, resp) = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Nvidia"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let nvidia_stock_id = resp.stock_id;
+    let sc = app
+        .clone()
+        .add_stock_to_user(
+            &vanguard_token,
+            AddStockToUserRequest {
+                stock_id: nvidia_stock_id.clone(),
+                quantity: 20,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // Only admins move stocks between phases
+    let sc = app
+        .clone()
+        .set_trading_phase(
+            &vanguard_token,
+            SetTradingPhaseRequest {
+                stock_id: nvidia_stock_id.clone(),
+                phase: TradingPhase::PreClose,
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+
+    // Continuous trading only ends into the closing call
+    let sc = app
+        .clone()
+        .set_trading_phase(
+            &admin_token,
+            SetTradingPhaseRequest {
+                stock_id: nvidia_stock_id.clone(),
+                phase: TradingPhase::PreOpen,
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Close with nothing to uncross and go into the pre-open call
+    for phase in [TradingPhase::PreClose, TradingPhase::PreOpen] {
+        let sc = app
+            .clone()
+            .set_trading_phase(
+                &admin_token,
+                SetTradingPhaseRequest {
+                    stock_id: nvidia_stock_id.clone(),
+                    phase,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::OK);
+    }
+
+    // Market orders can't wait for the auction
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: nvidia_stock_id.clone(),
+                is_buy: true,
+                order_type: OrderType::Market,
+                quantity: 1,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Vanguard sell 5 Nvidia at 50 and 5 at 52, User1 buy 8 at 53, all of
+    // it collecting without matching
+    for (token, is_buy, quantity, price) in [
+        (&vanguard_token, false, 5, 50),
+        (&vanguard_token, false, 5, 52),
+        (&user1_token, true, 8, 53),
+    ] {
+        let sc = app
+            .clone()
+            .place_stock_order(
+                token,
+                PlaceStockOrderRequest {
+                    stock_id: nvidia_stock_id.clone(),
+                    is_buy,
+                    order_type: OrderType::Limit,
+                    quantity,
+                    price: Some(price),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::CREATED);
+    }
+    let (sc, resp) = app
+        .clone()
+        .get_market_trades(&user1_token, &nvidia_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert!(resp.0.is_empty());
+
+    // 8 would trade at 52, the lowest of the prices crossing the most,
+    // leaving 2 offered
+    let (sc, resp) = app
+        .clone()
+        .get_auction(&user1_token, &nvidia_stock_id)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        resp,
+        AuctionIndicative {
+            stock_id: nvidia_stock_id.clone(),
+            phase: TradingPhase::PreOpen,
+            indicative_price: Some(52),
+            matched_volume: 8,
+            imbalance: 2,
+            imbalance_side: Some(Side::Sell),
+        }
+    );
+
+    // The opening auction prints at a single price without an aggressor
+    let sc = app
+        .clone()
+        .set_trading_phase(
+            &admin_token,
+            SetTradingPhaseRequest {
+                stock_id: nvidia_stock_id.clone(),
+                phase: TradingPhase::Continuous,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let (sc, resp) = app
+        .clone()
+        .get_market_trades(&user1_token, &nvidia_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        resp.0
+            .iter()
+            .map(|t| (t.seq, t.price, t.quantity, t.aggressor))
+            .collect::<Vec<_>>(),
+        vec![(1, 52, 5, None), (2, 52, 3, None)]
+    );
+    let (sc, resp) = app
+        .clone()
+        .get_order_book(&user1_token, &nvidia_stock_id, None)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        (resp.bids.is_empty(), resp.asks.first()),
+        (
+            true,
+            Some(&PriceLevel {
+                price: 52,
+                quantity: 2,
+                order_count: 1,
+            })
+        )
+    );
+
+    // In the closing call Vanguard sell 2 more at 55 and User1 buy 4 at 56
+    let sc = app
+        .clone()
+        .set_trading_phase(
+            &admin_token,
+            SetTradingPhaseRequest {
+                stock_id: nvidia_stock_id.clone(),
+                phase: TradingPhase::PreClose,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    for (token, is_buy, quantity, price) in
+        [(&vanguard_token, false, 2, 55), (&user1_token, true, 4, 56)]
+    {
+        let sc = app
+            .clone()
+            .place_stock_order(
+                token,
+                PlaceStockOrderRequest {
+                    stock_id: nvidia_stock_id.clone(),
+                    is_buy,
+                    order_type: OrderType::Limit,
+                    quantity,
+                    price: Some(price),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::CREATED);
+    }
+
+    // 55 and 56 both match all 4, 55 is closer to the last price
+    let (sc, resp) = app
+        .clone()
+        .get_auction(&user1_token, &nvidia_stock_id)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        (
+            resp.indicative_price,
+            resp.matched_volume,
+            resp.imbalance_side
+        ),
+        (Some(55), 4, None)
+    );
+
+    // The closing auction sets the official close, which only becomes the
+    // previous close the next day
+    let sc = app
+        .clone()
+        .set_trading_phase(
+            &admin_token,
+            SetTradingPhaseRequest {
+                stock_id: nvidia_stock_id.clone(),
+                phase: TradingPhase::PreOpen,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let (sc, resp) = app.clone().get_stock_prices(&user1_token).await.unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let nvidia = resp
+        .0
+        .iter()
+        .find(|s| s.stock_id == nvidia_stock_id)
+        .unwrap();
+    assert_eq!(
+        (nvidia.last_price, nvidia.previous_close, nvidia.day_change),
+        (Some(55), None, None)
+    );
+
+    // Circuit breakers need a positive move, window and cool-down
+    let sc = app
+        .clone()
+        .set_circuit_breaker(
+            &admin_token,
+            SetCircuitBreakerRequest {
+                stock_id: nvidia_stock_id.clone(),
+                circuit_breaker: Some(CircuitBreaker {
+                    max_move_percent: 0,
+                    window_secs: 60,
+                    halt_secs: 1,
+                }),
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Halt Nvidia for a second once it moves more than 10% within a minute
+    let sc = app
+        .clone()
+        .set_circuit_breaker(
+            &admin_token,
+            SetCircuitBreakerRequest {
+                stock_id: nvidia_stock_id.clone(),
+                circuit_breaker: Some(CircuitBreaker {
+                    max_move_percent: 10,
+                    window_secs: 60,
+                    halt_secs: 1,
+                }),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // Open Nvidia with nothing left to uncross, Vanguard sell 5 at 55, 2 at
+    // 62 and 1 at 70
+    let sc = app
+        .clone()
+        .set_trading_phase(
+            &admin_token,
+            SetTradingPhaseRequest {
+                stock_id: nvidia_stock_id.clone(),
+                phase: TradingPhase::Continuous,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    for (quantity, price) in [(5, 55), (2, 62), (1, 70)] {
+        let sc = app
+            .clone()
+            .place_stock_order(
+                &vanguard_token,
+                PlaceStockOrderRequest {
+                    stock_id: nvidia_stock_id.clone(),
+                    is_buy: false,
+                    order_type: OrderType::Limit,
+                    quantity,
+                    price: Some(price),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::CREATED);
+    }
+
+    // Only admins halt trading
+    let sc = app
+        .clone()
+        .halt_trading(
+            &user1_token,
+            TradingHaltRequest {
+                stock_id: nvidia_stock_id.clone(),
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+
+    // Halt Nvidia
+    let sc = app
+        .clone()
+        .halt_trading(
+            &admin_token,
+            TradingHaltRequest {
+                stock_id: nvidia_stock_id.clone(),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let trading_halted = |resp: &StockPriceVec| {
+        resp.0
+            .iter()
+            .find(|s| s.stock_id == nvidia_stock_id)
+            .map(|s| s.trading_halted)
+    };
+    let (_, resp) = app.clone().get_stock_prices(&user1_token).await.unwrap();
+    assert_eq!(trading_halted(&resp), Some(true));
+
+    // No orders while halted
+    let nvidia_buy = |quantity, price| PlaceStockOrderRequest {
+        stock_id: nvidia_stock_id.clone(),
+        is_buy: true,
+        order_type: OrderType::Limit,
+        quantity,
+        price: Some(price),
+        time_in_force: Some(TimeInForce::ImmediateOrCancel),
+        ..Default::default()
+    };
+    let sc = app
+        .clone()
+        .place_stock_order(&user1_token, nvidia_buy(1, 70))
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::CONFLICT);
+
+    // Cancels still go through
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    let nvidia_70 = resp
+        .0
+        .iter()
+        .find(|tx| tx.stock_id == nvidia_stock_id && tx.stock_price == 70)
+        .unwrap()
+        .stock_tx_id
+        .clone();
+    let sc = app
+        .clone()
+        .cancel_stock_order(
+            &vanguard_token,
+            CancelStockTransactionRequest {
+                stock_tx_id: nvidia_70,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    let sc = app
+        .clone()
+        .resume_trading(
+            &admin_token,
+            TradingHaltRequest {
+                stock_id: nvidia_stock_id.clone(),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    // User1 buy 6 Nvidia at up to 62, sweeping from 55 to 62 and tripping
+    // the circuit breaker
+    let sc = app
+        .clone()
+        .place_stock_order(&user1_token, nvidia_buy(6, 62))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let sc = app
+        .clone()
+        .place_stock_order(&user1_token, nvidia_buy(1, 62))
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::CONFLICT);
+    let (_, resp) = app.clone().get_stock_prices(&user1_token).await.unwrap();
+    assert_eq!(trading_halted(&resp), Some(true));
+
+    // Trading resumes after the cool-down
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let (_, resp) = app.clone().get_stock_prices(&user1_token).await.unwrap();
+    assert_eq!(trading_halted(&resp), Some(false));
+    let sc = app
+        .clone()
+        .place_stock_order(&user1_token, nvidia_buy(1, 62))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Amazon trades in ticks of 5 and lots of 10, at most 100 at a time
+    // and within 10% of the last trade
+    let amazon_rules = TradingRules {
+        tick_size: 5,
+        lot_size: 10,
+        max_order_quantity: Some(100),
+        price_collar_percent: Some(10),
+    };
+    let sc = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Amazon"),
+                rules: TradingRules {
+                    tick_size: 0,
+                    ..amazon_rules
+                },
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    let (sc, resp) = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Amazon"),
+                rules: amazon_rules,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let amazon_stock_id = resp.stock_id;
+    let sc = app
+        .clone()
+        .add_stock_to_user(
+            &vanguard_token,
+            AddStockToUserRequest {
+                stock_id: amazon_stock_id.clone(),
+                quantity: 200,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+
+    let amazon_order = |is_buy, quantity, price| PlaceStockOrderRequest {
+        stock_id: amazon_stock_id.clone(),
+        is_buy,
+        order_type: OrderType::Limit,
+        quantity,
+        price: Some(price),
+        ..Default::default()
+    };
+    // Off tick, an odd lot, too large and not positive
+    for (quantity, price) in [(10, 102), (15, 100), (110, 100), (0, 100), (10, -5)] {
+        let sc = app
+            .clone()
+            .place_stock_order(&vanguard_token, amazon_order(false, quantity, price))
+            .await
+            .unwrap_err();
+        assert_eq!(sc, StatusCode::BAD_REQUEST);
+    }
+
+    // No collar before the first trade: Vanguard sell 10 Amazon at 100 and
+    // User1 buy them
+    for (token, is_buy) in [(&vanguard_token, false), (&user1_token, true)] {
+        let sc = app
+            .clone()
+            .place_stock_order(token, amazon_order(is_buy, 10, 100))
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::CREATED);
+    }
+
+    // 115 is too far from the last trade at 100, 110 just makes it
+    let sc = app
+        .clone()
+        .place_stock_order(&vanguard_token, amazon_order(false, 10, 115))
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    let sc = app
+        .clone()
+        .place_stock_order(&vanguard_token, amazon_order(false, 10, 110))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Amendments follow the same rules
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&vanguard_token)
+        .await
+        .unwrap();
+    let amazon_110 = resp
+        .0
+        .iter()
+        .find(|tx| tx.stock_id == amazon_stock_id && tx.stock_price == 110)
+        .unwrap()
+        .stock_tx_id
+        .clone();
+    let sc = app
+        .clone()
+        .modify_stock_order(
+            &vanguard_token,
+            ModifyStockOrderRequest {
+                stock_tx_id: amazon_110.clone(),
+                price: Some(107),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Only admins change the trading rules
+    let sc = app
+        .clone()
+        .update_stock(
+            &vanguard_token,
+            UpdateStockRequest {
+                stock_id: amazon_stock_id.clone(),
+                rules: amazon_rules,
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+
+    // Down to ticks of 1 without a collar
+    let sc = app
+        .clone()
+        .update_stock(
+            &admin_token,
+            UpdateStockRequest {
+                stock_id: amazon_stock_id.clone(),
+                rules: TradingRules {
+                    tick_size: 1,
+                    price_collar_percent: None,
+                    ..amazon_rules
+                },
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let sc = app
+        .clone()
+        .modify_stock_order(
+            &vanguard_token,
+            ModifyStockOrderRequest {
+                stock_tx_id: amazon_110,
+                price: Some(107),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let sc = app
+        .clone()
+        .place_stock_order(&vanguard_token, amazon_order(false, 10, 151))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // Fee rates can't be negative
+    let pro = FeeSchedule {
+        tier: String::from("pro"),
+        maker_bps: 10,
+        taker_bps: 50,
+        min_fee: Some(2),
+    };
+    let sc = app
+        .clone()
+        .set_fee_schedule(
+            &admin_token,
+            FeeSchedule {
+                maker_bps: -1,
+                ..pro.clone()
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Only admins set fees
+    let sc = app
+        .clone()
+        .set_fee_schedule(&vanguard_token, pro.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+    let sc = app
+        .clone()
+        .set_fee_tier(
+            &vanguard_token,
+            SetFeeTierRequest {
+                user_name: String::from("VanguardETF"),
+                tier: String::from("standard"),
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+
+    // Pro accounts pay 0.1% for making and 0.5% for taking, at least 2 a
+    // trade
+    let sc = app
+        .clone()
+        .set_fee_schedule(&admin_token, pro)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let set_fee_tier = |user_name: &str, tier: &str| {
+        app.clone().set_fee_tier(
+            &admin_token,
+            SetFeeTierRequest {
+                user_name: user_name.to_string(),
+                tier: tier.to_string(),
+            },
+        )
+    };
+    let sc = set_fee_tier("FinanceGuru", "vip").await.unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    for user_name in ["FinanceGuru", "VanguardETF"] {
+        let sc = set_fee_tier(user_name, "pro").await.unwrap();
+        assert_eq!(sc, StatusCode::OK);
+    }
+
+    let sc = app
+        .clone()
+        .add_money_to_user(&user1_token, AddMoneyRequest { amount: 5_000 })
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+
+    // User1 take Vanguard's 10 Amazon at 107: 5 for the taker, the maker's
+    // 1 goes up to the minimum of 2
+    let (_, user1_before) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    let (_, vanguard_before) = app
+        .clone()
+        .get_wallet_balance(&vanguard_token)
+        .await
+        .unwrap();
+    let sc = app
+        .clone()
+        .place_stock_order(&user1_token, amazon_order(true, 10, 107))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, user1_after) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    let (_, vanguard_after) = app
+        .clone()
+        .get_wallet_balance(&vanguard_token)
+        .await
+        .unwrap();
+    assert_eq!(
+        (
+            user1_after.balance - user1_before.balance,
+            vanguard_after.balance - vanguard_before.balance
+        ),
+        (-1070 - 5, 1070 - 2)
+    );
+
+    // The fee is its own wallet entry, right after the trade
+    let (sc, resp) = app
+        .clone()
+        .get_wallet_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let [.., trade, fee] = &resp.0[..] else {
+        panic!("expected a trade and its fee, got {:?}", resp.0);
+    };
+    assert_eq!(
+        (trade.amount, trade.is_debit, fee.amount, fee.is_debit),
+        (1070, true, 5, true)
+    );
+    assert_eq!(trade.stock_tx_id, fee.stock_tx_id);
+
+    // A resting buy holds the larger fee on top of its notional
+    let sc = app
+        .clone()
+        .place_stock_order(&user1_token, amazon_order(true, 10, 100))
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(resp.held - user1_after.held, 1000 + 5);
+
+    for user_name in ["FinanceGuru", "VanguardETF"] {
+        let sc = set_fee_tier(user_name, "standard").await.unwrap();
+        assert_eq!(sc, StatusCode::OK);
+    }
+
+    // Withdrawals must be positive and can't exceed the available balance
+    let withdraw = |amount: i64| {
+        app.clone()
+            .withdraw_money(&user1_token, WithdrawMoneyRequest { amount })
+    };
+    let sc = withdraw(0).await.unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    let (_, before) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    let sc = withdraw(before.available + 1).await.unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Up to the approval threshold they go straight out, as a debit next to
+    // the trades
+    let (sc, resp) = withdraw(100).await.unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        (resp.user_name.as_str(), resp.amount, resp.status),
+        ("FinanceGuru", 100, WithdrawalStatus::Approved)
+    );
+    let (_, after) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (after.balance, after.held),
+        (before.balance - 100, before.held)
+    );
+    let (_, resp) = app
+        .clone()
+        .get_wallet_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_matches!(
+        resp.0.last(),
+        Some(WalletTransaction {
+            kind: EntryType::Withdrawal,
+            stock_tx_id: None,
+            is_debit: true,
+            amount: 100,
+            ..
+        })
+    );
+
+    // Above it they wait for an admin, holding the funds until then
+    let sc = app
+        .clone()
+        .add_money_to_user(&user1_token, AddMoneyRequest { amount: 30_000 })
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, before) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    let (_, resp) = withdraw(15_000).await.unwrap();
+    assert_eq!(resp.status, WithdrawalStatus::Pending);
+    let pending_id = resp.withdrawal_id;
+    let (_, after) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (after.balance, after.held),
+        (before.balance, before.held + 15_000)
+    );
+    let sc = withdraw(after.available + 1).await.unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    let (sc, resp) = app
+        .clone()
+        .get_pending_withdrawals(&admin_token)
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        resp.0
+            .iter()
+            .map(|w| (w.withdrawal_id.as_str(), w.user_name.as_str(), w.amount))
+            .collect::<Vec<_>>(),
+        vec![(pending_id.as_str(), "FinanceGuru", 15_000)]
+    );
+
+    // Only admins see and decide withdrawals, so nobody approves their own
+    let decision = || WithdrawalDecisionRequest {
+        withdrawal_id: pending_id.clone(),
+    };
+    let sc = app
+        .clone()
+        .get_pending_withdrawals(&user1_token)
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+    let sc = app
+        .clone()
+        .approve_withdrawal(&user1_token, decision())
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+    let sc = app
+        .clone()
+        .reject_withdrawal(&user1_token, decision())
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+    let sc = app
+        .clone()
+        .add_money_to_user(&admin_token, AddMoneyRequest { amount: 20_000 })
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, resp) = app
+        .clone()
+        .withdraw_money(&admin_token, WithdrawMoneyRequest { amount: 20_000 })
+        .await
+        .unwrap();
+    let own = WithdrawalDecisionRequest {
+        withdrawal_id: resp.withdrawal_id,
+    };
+    let sc = app
+        .clone()
+        .approve_withdrawal(&admin_token, own)
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+    let (_, resp) = app.clone().get_withdrawals(&admin_token).await.unwrap();
+    assert_eq!(
+        resp.0.iter().map(|w| w.status).collect::<Vec<_>>(),
+        vec![WithdrawalStatus::Pending]
+    );
+
+    // Approving it takes the money out, and it can only be decided once
+    let sc = app
+        .clone()
+        .approve_withdrawal(&admin_token, decision())
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let (_, resp) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (resp.balance, resp.held),
+        (before.balance - 15_000, before.held)
+    );
+    let sc = app
+        .clone()
+        .reject_withdrawal(&admin_token, decision())
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Rejecting one releases the funds it held
+    let (_, resp) = withdraw(12_000).await.unwrap();
+    assert_eq!(resp.status, WithdrawalStatus::Pending);
+    let sc = app
+        .clone()
+        .reject_withdrawal(
+            &admin_token,
+            WithdrawalDecisionRequest {
+                withdrawal_id: resp.withdrawal_id,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let (_, after) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(
+        (after.balance, after.held),
+        (before.balance - 15_000, before.held)
+    );
+    let (sc, resp) = app.clone().get_withdrawals(&user1_token).await.unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    assert_eq!(
+        resp.0
+            .iter()
+            .map(|w| (w.amount, w.status))
+            .collect::<Vec<_>>(),
+        vec![
+            (100, WithdrawalStatus::Approved),
+            (15_000, WithdrawalStatus::Approved),
+            (12_000, WithdrawalStatus::Rejected)
+        ]
+    );
+
+    // Admins correct balances with adjustments, which need a reason and
+    // can't debit more than is available
+    let adjust = |user_name: &str, amount: i64, reason: &str| {
+        app.clone().adjust_wallet(
+            &admin_token,
+            AdjustWalletRequest {
+                user_name: user_name.to_string(),
+                amount,
+                reason: reason.to_string(),
+            },
+        )
+    };
+    let sc = app
+        .clone()
+        .adjust_wallet(
+            &user1_token,
+            AdjustWalletRequest {
+                user_name: String::from("FinanceGuru"),
+                amount: 250,
+                reason: String::from("Outage compensation"),
+            },
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::FORBIDDEN);
+    let (_, before) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    for (user_name, amount, reason) in [
+        ("FinanceGuru", 250, ""),
+        ("FinanceGuru", 0, "Nothing"),
+        ("Nobody", 250, "Outage"),
+        ("FinanceGuru", -(before.available + 1), "Chargeback"),
+    ] {
+        let sc = adjust(user_name, amount, reason).await.unwrap_err();
+        assert_eq!(sc, StatusCode::BAD_REQUEST);
+    }
+    let sc = adjust("FinanceGuru", 250, "Outage compensation")
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let (_, after) = app.clone().get_wallet_balance(&user1_token).await.unwrap();
+    assert_eq!(after.balance, before.balance + 250);
+
+    // Every movement is in the wallet history, so it adds up to the balance
+    for token in [&user1_token, &vanguard_token] {
+        let (_, balance) = app.clone().get_wallet_balance(token).await.unwrap();
+        let (_, resp) = app.clone().get_wallet_transactions(token).await.unwrap();
+        let total: i64 = resp
+            .0
+            .iter()
+            .map(|tx| if tx.is_debit { -tx.amount } else { tx.amount })
+            .sum();
+        assert_eq!(total, balance.balance);
+        let mut ids: Vec<_> = resp.0.iter().map(|tx| &tx.wallet_tx_id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), resp.0.len());
+    }
+    let (_, resp) = app
+        .clone()
+        .get_wallet_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_matches!(
+        resp.0.last(),
+        Some(WalletTransaction {
+            kind: EntryType::Adjustment,
+            stock_tx_id: None,
+            is_debit: false,
+            amount: 250,
+            ..
+        })
+    );
+
+    // Histories come in pages, each pointing at the next until the last
+    let (_, all_trades) = app
+        .clone()
+        .get_stock_transactions_page(&user1_token, "limit=1000")
+        .await
+        .map(|(sc, resp, next_cursor)| {
+            assert_eq!((sc, next_cursor), (StatusCode::OK, None));
+            (sc, resp)
+        })
+        .unwrap();
+    let mut paged = vec![];
+    let mut query = String::from("limit=3");
+    loop {
+        let (sc, resp, next_cursor) = app
+            .clone()
+            .get_stock_transactions_page(&user1_token, &query)
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::OK);
+        assert!(resp.0.len() <= 3);
+        paged.extend(resp.0);
+        let Some(cursor) = next_cursor else { break };
+        query = format!("limit=3&after={cursor}");
+    }
+    assert_eq!(
+        paged.iter().map(|tx| &tx.stock_tx_id).collect::<Vec<_>>(),
+        all_trades
+            .0
+            .iter()
+            .map(|tx| &tx.stock_tx_id)
+            .collect::<Vec<_>>()
+    );
+    let (_, all_wallet) = app
+        .clone()
+        .get_wallet_transactions(&user1_token)
+        .await
+        .unwrap();
+    let mut paged = vec![];
+    let mut query = String::from("limit=4");
+    loop {
+        let (_, resp, next_cursor) = app
+            .clone()
+            .get_wallet_transactions_page(&user1_token, &query)
+            .await
+            .unwrap();
+        paged.extend(resp.0);
+        let Some(cursor) = next_cursor else { break };
+        query = format!("limit=4&after={cursor}");
+    }
+    assert_eq!(
+        paged.iter().map(|tx| &tx.wallet_tx_id).collect::<Vec<_>>(),
+        all_wallet
+            .0
+            .iter()
+            .map(|tx| &tx.wallet_tx_id)
+            .collect::<Vec<_>>()
+    );
+
+    // Page sizes are bounded and cursors have to be ones handed out
+    for query in ["limit=0", "limit=1001", "after=yesterday", "after=1_2"] {
+        let sc = app
+            .clone()
+            .get_stock_transactions_page(&user1_token, query)
+            .await
+            .unwrap_err();
+        assert_eq!(sc, StatusCode::BAD_REQUEST);
+    }
+
+    // Filtered by stock, side, type and status
+    let (_, resp, _) = app
+        .clone()
+        .get_stock_transactions_page(
+            &user1_token,
+            &format!("stock_id={amazon_stock_id}&side=BUY&order_type=LIMIT&order_status=COMPLETED"),
+        )
+        .await
+        .unwrap();
+    assert!(!resp.0.is_empty());
+    assert!(resp.0.iter().all(|tx| tx.stock_id == amazon_stock_id
+        && tx.is_buy
+        && tx.order_type == OrderType::Limit
+        && tx.order_status == OrderStatus::Completed));
+    assert_eq!(
+        resp.0.len(),
+        all_trades
+            .0
+            .iter()
+            .filter(|tx| tx.stock_id == amazon_stock_id
+                && tx.is_buy
+                && tx.order_type == OrderType::Limit
+                && tx.order_status == OrderStatus::Completed)
+            .count()
+    );
+
+    // Wallet transactions by kind, and through their order by stock
+    let (_, resp, _) = app
+        .clone()
+        .get_wallet_transactions_page(&user1_token, "kind=FEE")
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.0.iter().map(|tx| tx.kind).collect::<Vec<_>>(),
+        vec![EntryType::Fee]
+    );
+    let (_, resp, _) = app
+        .clone()
+        .get_wallet_transactions_page(&user1_token, &format!("stock_id={amazon_stock_id}"))
+        .await
+        .unwrap();
+    let amazon_orders: Vec<_> = all_trades
+        .0
+        .iter()
+        .filter(|tx| tx.stock_id == amazon_stock_id)
+        .map(|tx| Some(tx.stock_tx_id.clone()))
+        .collect();
+    assert!(!resp.0.is_empty());
+    assert!(
+        resp.0
+            .iter()
+            .all(|tx| amazon_orders.contains(&tx.stock_tx_id))
+    );
+    assert_eq!(
+        resp.0.iter().filter(|tx| tx.kind == EntryType::Fee).count(),
+        1
+    );
+
+    // And by time, from inclusive to exclusive
+    let from = all_wallet.0[1].time_stamp;
+    let to = all_wallet.0.last().unwrap().time_stamp;
+    let (_, resp, _) = app
+        .clone()
+        .get_wallet_transactions_page(
+            &user1_token,
+            &format!(
+                "from={}&to={}",
+                from.to_rfc3339_opts(SecondsFormat::Micros, true),
+                to.to_rfc3339_opts(SecondsFormat::Micros, true)
+            ),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        resp.0.iter().map(|tx| &tx.wallet_tx_id).collect::<Vec<_>>(),
+        all_wallet
+            .0
+            .iter()
+            .filter(|tx| tx.time_stamp >= from && tx.time_stamp < to)
+            .map(|tx| &tx.wallet_tx_id)
+            .collect::<Vec<_>>()
+    );
+    // Netflix between a new account and Vanguard, both paying pro fees.
+    // IndexTracker buys 10 at 100 and 10 at 120 as the taker, for 5 and 6,
+    // then sells 15 at 130 as the maker for the minimum 2
+    let sc = app
+        .clone()
+        .register(RegisterRequest {
+            user_name: String::from("IndexTracker"),
+            password: String::from("Index@2024"),
+            name: String::from("Index Tracker"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, resp) = app
+        .clone()
+        .login(LoginRequest {
+            user_name: String::from("IndexTracker"),
+            password: String::from("Index@2024"),
+        })
+        .await
+        .unwrap();
+    let user2_token = resp.token;
+    let (sc, resp) = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Netflix"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let netflix_stock_id = resp.stock_id;
+    let sc = app
+        .clone()
+        .add_stock_to_user(
+            &vanguard_token,
+            AddStockToUserRequest {
+                stock_id: netflix_stock_id.clone(),
+                quantity: 20,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    for token in [&user2_token, &vanguard_token] {
+        let sc = app
+            .clone()
+            .add_money_to_user(token, AddMoneyRequest { amount: 5_000 })
+            .await
+            .unwrap();
+        assert_eq!(sc, StatusCode::CREATED);
+    }
+    for user_name in ["IndexTracker", "VanguardETF"] {
+        let sc = set_fee_tier(user_name, "pro").await.unwrap();
+        assert_eq!(sc, StatusCode::OK);
+    }
+    let netflix_order = |is_buy, quantity, price| PlaceStockOrderRequest {
+        stock_id: netflix_stock_id.clone(),
+        is_buy,
+        order_type: OrderType::Limit,
+        quantity,
+        price: Some(price),
+        ..Default::default()
+    };
+    for (seller, buyer, quantity, price) in [
+        (&vanguard_token, &user2_token, 10, 100),
+        (&vanguard_token, &user2_token, 10, 120),
+        (&user2_token, &vanguard_token, 15, 130),
+    ] {
+        for (token, is_buy) in [(seller, false), (buyer, true)] {
+            let sc = app
+                .clone()
+                .place_stock_order(token, netflix_order(is_buy, quantity, price))
+                .await
+                .unwrap();
+            assert_eq!(sc, StatusCode::CREATED);
+        }
+    }
+    for user_name in ["IndexTracker", "VanguardETF"] {
+        let sc = set_fee_tier(user_name, "standard").await.unwrap();
+        assert_eq!(sc, StatusCode::OK);
+    }
+
+    // 5 left worth 650. FIFO sold the 1005 lot and half the 1206 one, LIFO
+    // the 1206 lot and half the 1005 one, and the average a share of 2211.
+    // Whichever it is, all the profit comes to 2600 - 2200 - 13
+    let netflix = |cost_basis, realized_pnl| StockPortfolio {
+        stock_id: netflix_stock_id.clone(),
+        stock_name: String::from("Netflix"),
+        quantity_owned: 5,
+        average_cost: Some(cost_basis / 5),
+        cost_basis,
+        market_value: Some(650),
+        unrealized_pnl: Some(650 - cost_basis),
+        realized_pnl,
+    };
+    for (query, cost_basis, realized_pnl) in [
+        ("", 603, 1950 - 2 - (1005 + 603)),
+        ("lot_method=LIFO", 503, 1950 - 2 - (1206 + 502)),
+        ("lot_method=AVERAGE", 553, 1950 - 2 - 1658),
+    ] {
+        let (sc, resp) = app
+            .clone()
+            .get_stock_portfolio_with(&user2_token, query)
+            .await
+            .unwrap();
+        assert_eq!(
+            (sc, resp.0),
+            (StatusCode::OK, vec![netflix(cost_basis, realized_pnl)])
+        );
+        let (sc, resp) = app
+            .clone()
+            .get_portfolio_summary(&user2_token, query)
+            .await
+            .unwrap();
+        assert_eq!(
+            (sc, &resp),
+            (
+                StatusCode::OK,
+                &PortfolioSummary {
+                    cost_basis,
+                    market_value: 650,
+                    unrealized_pnl: 650 - cost_basis,
+                    realized_pnl,
+                }
+            )
+        );
+        assert_eq!(resp.unrealized_pnl + resp.realized_pnl, 2600 - 2200 - 13);
+    }
+    let sc = app
+        .clone()
+        .get_portfolio_summary(&user2_token, "lot_method=HIFO")
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+
+    // Vanguard sold its 20 from setup, which cost nothing and make no
+    // profit, and bought 15 back for 1950 and a taker fee of 9
+    let (_, resp) = app
+        .clone()
+        .get_stock_portfolio(&vanguard_token)
+        .await
+        .unwrap();
+    assert_matches!(
+        resp.0.iter().find(|i| i.stock_id == netflix_stock_id),
+        Some(StockPortfolio {
+            quantity_owned: 15,
+            average_cost: Some(130),
+            cost_basis: 1959,
+            unrealized_pnl: Some(-9),
+            realized_pnl: 0,
+            ..
+        })
+    );
+
+    // Users can only cancel their own orders by default, with
+    // CANCEL_ANY_ORDER=true Vanguard can cancel User1's order too
+    let (_, resp) = app
+        .clone()
+        .create_stock(
+            &vanguard_token,
+            CreateStockRequest {
+                stock_name: String::from("Intel"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let intel_stock_id = resp.stock_id;
+    let sc = app
+        .clone()
+        .place_stock_order(
+            &user1_token,
+            PlaceStockOrderRequest {
+                stock_id: intel_stock_id,
+                is_buy: true,
+                order_type: OrderType::Limit,
+                quantity: 1,
+                price: Some(10),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::CREATED);
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    let user1_intel_bid = resp.0.last().unwrap().stock_tx_id.clone();
+    let cancel = || CancelStockTransactionRequest {
+        stock_tx_id: user1_intel_bid.clone(),
+    };
+    let sc = app
+        .clone()
+        .cancel_stock_order(&vanguard_token, cancel())
+        .await
+        .unwrap_err();
+    assert_eq!(sc, StatusCode::BAD_REQUEST);
+    let any_app = App::with_db(DB::init().await.unwrap().with_cancel_any_order(true)).await;
+    let sc = any_app
+        .clone()
+        .cancel_stock_order(&vanguard_token, cancel())
+        .await
+        .unwrap();
+    assert_eq!(sc, StatusCode::OK);
+    let (_, resp) = app
+        .clone()
+        .get_stock_transactions(&user1_token)
+        .await
+        .unwrap();
+    assert_matches!(
+        resp.0.last(),
+        Some(StockTransaction {
+            stock_tx_id,
+            order_status: OrderStatus::Cancelled,
+            ..
+        }) if *stock_tx_id == user1_intel_bid
+    );
+
+    // The previous close is whichever came last before today, Nvidia's
+    // closing auction at 55 or its last trade
+    let pool = sqlx::PgPool::connect(&std::env::var("DB_ENDPOINT").unwrap())
+        .await
+        .unwrap();
+    let backdate = |table: &'static str, days: i32| {
+        let pool = pool.clone();
+        let stock_id: i64 = nvidia_stock_id.parse().unwrap();
+        async move {
+            sqlx::query(&format!(
+                "UPDATE {table} SET created_at = LOCALTIMESTAMP - make_interval(days => $1) WHERE stock_id = $2"
+            ))
+            .bind(days)
+            .bind(stock_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+    };
+    let previous_close = || async {
+        let (_, resp) = app.clone().get_stock_prices(&user1_token).await.unwrap();
+        let nvidia = resp
+            .0
+            .into_iter()
+            .find(|s| s.stock_id == nvidia_stock_id)
+            .unwrap();
+        (nvidia.last_price, nvidia.previous_close, nvidia.day_change)
+    };
+    backdate("auctions", 7).await;
+    backdate("trades", 1).await;
+    assert_eq!(previous_close().await, (Some(62), Some(62), Some(0)));
+    backdate("trades", 14).await;
+    assert_eq!(previous_close().await, (Some(62), Some(55), Some(7)));
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApiResponseWrapper<T> {
+    success: bool,
+    data: T,
+    #[serde(default)]
+    next_cursor: Option<String>,
+}
+
+#[derive(Clone)]
+struct App {
+    app: RouterIntoService<Body>,
+}
+
+impl App {
+    async fn init() -> Self {
+        Self::with_db(DB::init().await.unwrap()).await
+    }
+
+    async fn with_db(db: DB) -> Self {
+        let state = AppState {
+            engine: Engine::init(db.clone()).await.unwrap(),
+            db,
+        };
+
+        App {
+            app: router(state).await.into_service(),
+        }
+    }
+
+    async fn request<B: Serialize, R: for<'a> de::Deserialize<'a>>(
+        self,
+        token: &String,
+        request: Builder,
+        payload: Option<B>,
+    ) -> Result<(StatusCode, R), StatusCode> {
+        let (sc, data, _next_cursor) = self.request_page(token, request, payload).await?;
+        Ok((sc, data))
+    }
+
+    /// Like `request`, along with the cursor of the next page of a history
+    async fn request_page<B: Serialize, R: for<'a> de::Deserialize<'a>>(
+        mut self,
+        token: &String,
+        request: Builder,
+        payload: Option<B>,
+    ) -> Result<(StatusCode, R, Option<String>), StatusCode> {
+        let request = request.header("token", token);
+        let request = if let Some(ref p) = payload {
+            request
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_string(&p).unwrap()))
+        } else {
+            request.body(Body::empty())
+        }
+        .unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut self.app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+
+        let (_parts, rawbody) = response.into_parts();
+        let bytes = axum::body::to_bytes(rawbody, usize::MAX).await.unwrap();
+        let obj: ApiResponseWrapper<R> =
+            serde_json::from_slice(&bytes).map_err(|_| _parts.status)?;
+
+        Ok((_parts.status, obj.data, obj.next_cursor))
+    }
+
+    async fn register(self, payload: RegisterRequest) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                &"".to_string(),
+                Request::builder()
+                    .uri("/authentication/register")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn login(self, payload: LoginRequest) -> Result<(StatusCode, TokenResponse), StatusCode> {
+        let resp = self
+            .request::<_, TokenResponse>(
+                &"".to_string(),
+                Request::builder()
+                    .uri("/authentication/login")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(resp)
+    }
+
+    async fn create_stock(
+        self,
+        token: &String,
+        payload: CreateStockRequest,
+    ) -> Result<(StatusCode, StockId), StatusCode> {
+        let resp = self
+            .request::<_, StockId>(
+                token,
+                Request::builder().uri("/setup/createStock").method("POST"),
+                Some(payload),
+            )
             .await?;
 
         Ok(resp)
     }
 
-    async fn create_stock(
+    async fn update_stock(
         self,
         token: &String,
-        payload: CreateStockRequest,
-    ) -> Result<(StatusCode, StockId), StatusCode> {
-        let resp = self
-            .request::<_, StockId>(
+        payload: UpdateStockRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
                 token,
-                Request::builder().uri("/setup/createStock").method("POST"),
+                Request::builder().uri("/setup/updateStock").method("POST"),
                 Some(payload),
             )
             .await?;
 
-        Ok(resp)
+        Ok(sc)
+    }
+
+    async fn set_fee_schedule(
+        self,
+        token: &String,
+        payload: FeeSchedule,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/setup/setFeeSchedule")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn set_fee_tier(
+        self,
+        token: &String,
+        payload: SetFeeTierRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder().uri("/setup/setFeeTier").method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
     }
 
     async fn add_stock_to_user(
@@ -1210,6 +4468,106 @@ impl App {
         Ok(sc)
     }
 
+    async fn adjust_wallet(
+        self,
+        token: &String,
+        payload: AdjustWalletRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder().uri("/setup/adjustWallet").method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn withdraw_money(
+        self,
+        token: &String,
+        payload: WithdrawMoneyRequest,
+    ) -> Result<(StatusCode, Withdrawal), StatusCode> {
+        let (sc, resp) = self
+            .request::<_, Withdrawal>(
+                token,
+                Request::builder()
+                    .uri("/transaction/withdrawMoneyFromWallet")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok((sc, resp))
+    }
+
+    async fn get_withdrawals(
+        self,
+        token: &String,
+    ) -> Result<(StatusCode, WithdrawalVec), StatusCode> {
+        let (sc, resp) = self
+            .request::<_, WithdrawalVec>(
+                token,
+                Request::builder().uri("/transaction/getWithdrawals"),
+                None::<i64>,
+            )
+            .await?;
+
+        Ok((sc, resp))
+    }
+
+    async fn get_pending_withdrawals(
+        self,
+        token: &String,
+    ) -> Result<(StatusCode, WithdrawalVec), StatusCode> {
+        let (sc, resp) = self
+            .request::<_, WithdrawalVec>(
+                token,
+                Request::builder().uri("/setup/getPendingWithdrawals"),
+                None::<i64>,
+            )
+            .await?;
+
+        Ok((sc, resp))
+    }
+
+    async fn approve_withdrawal(
+        self,
+        token: &String,
+        payload: WithdrawalDecisionRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/setup/approveWithdrawal")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn reject_withdrawal(
+        self,
+        token: &String,
+        payload: WithdrawalDecisionRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/setup/rejectWithdrawal")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
     async fn get_stock_prices(
         self,
         token: &String,
@@ -1225,6 +4583,142 @@ impl App {
         Ok((sc, resp))
     }
 
+    async fn get_order_book(
+        self,
+        token: &String,
+        stock_id: &String,
+        depth: Option<i64>,
+    ) -> Result<(StatusCode, OrderBookDepth), StatusCode> {
+        let uri = match depth {
+            Some(depth) => format!("/market/orderBook?stock_id={stock_id}&depth={depth}"),
+            None => format!("/market/orderBook?stock_id={stock_id}"),
+        };
+        let (sc, resp) = self
+            .request::<_, OrderBookDepth>(token, Request::builder().uri(uri), None::<i64>)
+            .await?;
+
+        Ok((sc, resp))
+    }
+
+    async fn get_market_trades(
+        self,
+        token: &String,
+        stock_id: &String,
+        since_seq: Option<i64>,
+    ) -> Result<(StatusCode, MarketTradeVec), StatusCode> {
+        let uri = match since_seq {
+            Some(since_seq) => format!("/market/trades?stock_id={stock_id}&since_seq={since_seq}"),
+            None => format!("/market/trades?stock_id={stock_id}"),
+        };
+        let (sc, resp) = self
+            .request::<_, MarketTradeVec>(token, Request::builder().uri(uri), None::<i64>)
+            .await?;
+
+        Ok((sc, resp))
+    }
+
+    async fn get_candles(
+        self,
+        token: &String,
+        query: &String,
+    ) -> Result<(StatusCode, CandleVec), StatusCode> {
+        let (sc, resp) = self
+            .request::<_, CandleVec>(
+                token,
+                Request::builder().uri(format!("/market/candles?{query}")),
+                None::<i64>,
+            )
+            .await?;
+
+        Ok((sc, resp))
+    }
+
+    async fn set_trading_phase(
+        self,
+        token: &String,
+        payload: SetTradingPhaseRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/setup/setTradingPhase")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn halt_trading(
+        self,
+        token: &String,
+        payload: TradingHaltRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder().uri("/setup/haltTrading").method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn resume_trading(
+        self,
+        token: &String,
+        payload: TradingHaltRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/setup/resumeTrading")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn set_circuit_breaker(
+        self,
+        token: &String,
+        payload: SetCircuitBreakerRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/setup/setCircuitBreaker")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
+    async fn get_auction(
+        self,
+        token: &String,
+        stock_id: &String,
+    ) -> Result<(StatusCode, AuctionIndicative), StatusCode> {
+        let (sc, resp) = self
+            .request::<_, AuctionIndicative>(
+                token,
+                Request::builder().uri(format!("/market/auction?stock_id={stock_id}")),
+                None::<i64>,
+            )
+            .await?;
+
+        Ok((sc, resp))
+    }
+
     async fn get_stock_portfolio(
         self,
         token: &String,
@@ -1240,6 +4734,32 @@ impl App {
         Ok((sc, resp))
     }
 
+    async fn get_stock_portfolio_with(
+        self,
+        token: &String,
+        query: &str,
+    ) -> Result<(StatusCode, StockPortfolioVec), StatusCode> {
+        self.request::<_, StockPortfolioVec>(
+            token,
+            Request::builder().uri(format!("/transaction/getStockPortfolio?{query}")),
+            None::<i64>,
+        )
+        .await
+    }
+
+    async fn get_portfolio_summary(
+        self,
+        token: &String,
+        query: &str,
+    ) -> Result<(StatusCode, PortfolioSummary), StatusCode> {
+        self.request::<_, PortfolioSummary>(
+            token,
+            Request::builder().uri(format!("/transaction/getPortfolioSummary?{query}")),
+            None::<i64>,
+        )
+        .await
+    }
+
     async fn get_wallet_balance(self, token: &String) -> Result<(StatusCode, Balance), StatusCode> {
         let (sc, resp) = self
             .request::<_, Balance>(
@@ -1267,6 +4787,32 @@ impl App {
         Ok((sc, resp))
     }
 
+    async fn get_stock_transactions_page(
+        self,
+        token: &String,
+        query: &str,
+    ) -> Result<(StatusCode, TradeVec, Option<String>), StatusCode> {
+        self.request_page::<_, TradeVec>(
+            token,
+            Request::builder().uri(format!("/transaction/getStockTransactions?{query}")),
+            None::<i64>,
+        )
+        .await
+    }
+
+    async fn get_wallet_transactions_page(
+        self,
+        token: &String,
+        query: &str,
+    ) -> Result<(StatusCode, WalletVec, Option<String>), StatusCode> {
+        self.request_page::<_, WalletVec>(
+            token,
+            Request::builder().uri(format!("/transaction/getWalletTransactions?{query}")),
+            None::<i64>,
+        )
+        .await
+    }
+
     async fn get_wallet_transactions(
         self,
         token: &String,
@@ -1300,6 +4846,24 @@ impl App {
         Ok(sc)
     }
 
+    async fn modify_stock_order(
+        self,
+        token: &String,
+        payload: ModifyStockOrderRequest,
+    ) -> Result<StatusCode, StatusCode> {
+        let (sc, _resp) = self
+            .request::<_, Option<i64>>(
+                token,
+                Request::builder()
+                    .uri("/engine/modifyStockOrder")
+                    .method("POST"),
+                Some(payload),
+            )
+            .await?;
+
+        Ok(sc)
+    }
+
     async fn cancel_stock_order(
         self,
         token: &String,
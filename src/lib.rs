@@ -1,5 +1,3 @@
-#![feature(assert_matches)]
-#![feature(duration_constructors)]
 use std::any::Any;
 
 use axum::{
@@ -18,13 +16,16 @@ use crate::{db::DB, frontend::home, telemetry::otel_tracing, types::AppState};
 
 pub mod admin;
 pub mod auth;
+pub mod book;
 pub mod db;
+pub mod engine;
 pub mod frontend;
 pub mod hypertxt;
 #[cfg(test)]
 pub mod integration;
 pub mod market;
 pub mod order;
+pub mod portfolio;
 pub mod telemetry;
 pub mod types;
 pub mod user;
@@ -42,6 +43,10 @@ pub async fn router(state: AppState) -> Router {
             "/transaction/getStockPortfolio",
             get(market::get_stock_portfolio),
         )
+        .route(
+            "/transaction/getPortfolioSummary",
+            get(market::get_portfolio_summary),
+        )
         .route(
             "/transaction/getWalletBalance",
             get(market::get_wallet_balance),
@@ -54,8 +59,14 @@ pub async fn router(state: AppState) -> Router {
             "/transaction/getStockTransactions",
             get(market::get_stock_transactions),
         )
+        .route("/transaction/getWithdrawals", get(market::get_withdrawals))
+        .route("/market/orderBook", get(market::get_order_book))
+        .route("/market/trades", get(market::get_market_trades))
+        .route("/market/candles", get(market::get_candles))
+        .route("/market/auction", get(market::get_auction))
         // Order
         .route("/engine/placeStockOrder", post(order::place_stock_order))
+        .route("/engine/modifyStockOrder", post(order::modify_stock_order))
         .route(
             "/engine/cancelStockTransaction",
             post(order::cancel_stock_transaction),
@@ -65,8 +76,26 @@ pub async fn router(state: AppState) -> Router {
             "/transaction/addMoneyToWallet",
             post(admin::add_money_to_wallet),
         )
+        .route(
+            "/transaction/withdrawMoneyFromWallet",
+            post(admin::withdraw_money_from_wallet),
+        )
+        .route(
+            "/setup/getPendingWithdrawals",
+            get(admin::get_pending_withdrawals),
+        )
+        .route("/setup/approveWithdrawal", post(admin::approve_withdrawal))
+        .route("/setup/adjustWallet", post(admin::adjust_wallet))
+        .route("/setup/rejectWithdrawal", post(admin::reject_withdrawal))
         .route("/setup/addStockToUser", post(admin::add_stock_to_user))
         .route("/setup/createStock", post(admin::create_stock))
+        .route("/setup/updateStock", post(admin::update_stock))
+        .route("/setup/setFeeSchedule", post(admin::set_fee_schedule))
+        .route("/setup/setFeeTier", post(admin::set_fee_tier))
+        .route("/setup/setTradingPhase", post(admin::set_trading_phase))
+        .route("/setup/haltTrading", post(admin::halt_trading))
+        .route("/setup/resumeTrading", post(admin::resume_trading))
+        .route("/setup/setCircuitBreaker", post(admin::set_circuit_breaker))
         // Misc
         .layer(otel_tracing())
         .route("/health", get(healthcheck))
@@ -1,13 +1,15 @@
 use axum::serve;
 use tracing::info;
-use trade::{db::DB, router, telemetry::tracing_init, types::AppState};
+use trade::{db::DB, engine::Engine, router, telemetry::tracing_init, types::AppState};
 
 #[tokio::main]
 async fn main() {
     tracing_init(env!("CARGO_PKG_NAME"), env!("GIT_HASH"));
 
+    let db = DB::init().await.unwrap();
     let state = AppState {
-        db: DB::init().await.unwrap(),
+        engine: Engine::init(db.clone()).await.unwrap(),
+        db,
     };
 
     let app = router(state).await;
@@ -1,11 +1,28 @@
-use axum::extract::State;
+use axum::extract::{Query, State};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     AppState,
     auth::AuthUser,
-    types::{AppError, Balance, StockPortfolioVec, StockPriceVec, TradeVec, WalletVec},
+    types::{
+        AppError, AuctionIndicative, Balance, CandleInterval, CandleVec, EntryType, HistoryFilter,
+        LotMethod, MarketTradeVec, OrderBookDepth, OrderStatus, OrderType, Page, PortfolioSummary,
+        Side, StockPortfolioVec, StockPriceVec, TradeVec, WalletVec, WithdrawalVec,
+    },
 };
 
+/// Price levels returned per side when `depth` isn't given
+const DEFAULT_BOOK_DEPTH: i64 = 10;
+const MAX_BOOK_DEPTH: i64 = 100;
+/// Prints returned per trade tape request, clients page with `since_seq`
+const TRADE_TAPE_PAGE: i64 = 500;
+/// Most bars returned per candles request
+const MAX_CANDLES: i64 = 1000;
+/// Transactions returned per history page when `limit` isn't given
+const DEFAULT_HISTORY_PAGE: i64 = 100;
+const MAX_HISTORY_PAGE: i64 = 1000;
+
 #[tracing::instrument(skip_all)]
 pub async fn get_stock_prices(
     AuthUser(_user): AuthUser,
@@ -19,34 +36,229 @@ pub async fn get_stock_prices(
 pub async fn get_stock_portfolio(
     AuthUser(user): AuthUser,
     State(state): State<AppState>,
+    Query(query): Query<PortfolioQuery>,
 ) -> Result<StockPortfolioVec, AppError> {
-    let out = state.db.get_stock_portfolio(user).await?;
+    let out = state
+        .db
+        .get_stock_portfolio(user, query.lot_method.unwrap_or_default())
+        .await?;
     Ok(StockPortfolioVec(out))
 }
 
+#[derive(Serialize, Deserialize, Default)]
+pub struct PortfolioQuery {
+    /// Defaults to FIFO
+    pub lot_method: Option<LotMethod>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_portfolio_summary(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<PortfolioQuery>,
+) -> Result<PortfolioSummary, AppError> {
+    let out = state
+        .db
+        .get_portfolio_summary(user, query.lot_method.unwrap_or_default())
+        .await?;
+    Ok(out)
+}
+
 #[tracing::instrument(skip_all)]
 pub async fn get_wallet_balance(
     AuthUser(user): AuthUser,
     State(state): State<AppState>,
 ) -> Result<Balance, AppError> {
-    let bal = state.db.get_wallet_balance(user).await?;
-    Ok(Balance { balance: bal })
+    let out = state.db.get_wallet_balance(user).await?;
+    Ok(out)
+}
+
+/// Page and filters of a user's stock or wallet transactions, oldest first
+#[derive(Serialize, Deserialize, Default)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page
+    pub after: Option<String>,
+    pub stock_id: Option<String>,
+    pub side: Option<Side>,
+    pub order_status: Option<OrderStatus>,
+    pub order_type: Option<OrderType>,
+    /// Only for wallet transactions
+    pub kind: Option<EntryType>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl HistoryQuery {
+    fn limit(&self) -> Result<i64, AppError> {
+        let limit = self.limit.unwrap_or(DEFAULT_HISTORY_PAGE);
+        if !(1..=MAX_HISTORY_PAGE).contains(&limit) {
+            return Err(AppError::BadRequest);
+        }
+        Ok(limit)
+    }
+
+    fn filter(&self) -> Result<HistoryFilter, AppError> {
+        let stock_id = self
+            .stock_id
+            .as_ref()
+            .map(|id| id.parse().map_err(|_| AppError::StockNotFound))
+            .transpose()?;
+        Ok(HistoryFilter {
+            stock_id,
+            side: self.side,
+            order_status: self.order_status,
+            order_type: self.order_type,
+            kind: self.kind,
+            from: self.from,
+            to: self.to,
+        })
+    }
 }
 
 #[tracing::instrument(skip_all)]
 pub async fn get_wallet_transactions(
     AuthUser(user): AuthUser,
     State(state): State<AppState>,
-) -> Result<WalletVec, AppError> {
-    let out = state.db.get_wallet_transactions(user).await?;
-    Ok(WalletVec(out))
+    Query(query): Query<HistoryQuery>,
+) -> Result<Page<WalletVec>, AppError> {
+    let (out, next_cursor) = state
+        .db
+        .get_wallet_transactions(
+            user,
+            &query.filter()?,
+            query.after.as_deref(),
+            query.limit()?,
+        )
+        .await?;
+    Ok(Page {
+        data: WalletVec(out),
+        next_cursor,
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_withdrawals(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+) -> Result<WithdrawalVec, AppError> {
+    let out = state.db.get_withdrawals(Some(user)).await?;
+    Ok(WithdrawalVec(out))
 }
 
 #[tracing::instrument(skip_all)]
 pub async fn get_stock_transactions(
     AuthUser(user): AuthUser,
     State(state): State<AppState>,
-) -> Result<TradeVec, AppError> {
-    let out = state.db.get_stock_transactions(user).await?;
-    Ok(TradeVec(out))
+    Query(query): Query<HistoryQuery>,
+) -> Result<Page<TradeVec>, AppError> {
+    let (out, next_cursor) = state
+        .db
+        .get_stock_transactions(
+            user,
+            &query.filter()?,
+            query.after.as_deref(),
+            query.limit()?,
+        )
+        .await?;
+    Ok(Page {
+        data: TradeVec(out),
+        next_cursor,
+    })
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct OrderBookQuery {
+    pub stock_id: String,
+    pub depth: Option<i64>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_order_book(
+    AuthUser(_user): AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<OrderBookQuery>,
+) -> Result<OrderBookDepth, AppError> {
+    let depth = query.depth.unwrap_or(DEFAULT_BOOK_DEPTH);
+    if !(1..=MAX_BOOK_DEPTH).contains(&depth) {
+        return Err(AppError::BadRequest);
+    }
+    let stock_id = query
+        .stock_id
+        .parse()
+        .map_err(|_| AppError::StockNotFound)?;
+
+    let out = state.db.get_order_book(stock_id, depth).await?;
+    Ok(out)
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct MarketTradesQuery {
+    pub stock_id: String,
+    /// Last sequence number already seen, 0 to start from the first print
+    #[serde(default)]
+    pub since_seq: i64,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_market_trades(
+    AuthUser(_user): AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<MarketTradesQuery>,
+) -> Result<MarketTradeVec, AppError> {
+    let stock_id = query
+        .stock_id
+        .parse()
+        .map_err(|_| AppError::StockNotFound)?;
+
+    let out = state
+        .db
+        .get_market_trades(stock_id, query.since_seq, TRADE_TAPE_PAGE)
+        .await?;
+    Ok(MarketTradeVec(out))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CandlesQuery {
+    pub stock_id: String,
+    pub interval: CandleInterval,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_candles(
+    AuthUser(_user): AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<CandlesQuery>,
+) -> Result<CandleVec, AppError> {
+    let stock_id = query
+        .stock_id
+        .parse()
+        .map_err(|_| AppError::StockNotFound)?;
+
+    let out = state
+        .db
+        .get_candles(stock_id, query.interval, query.from, query.to, MAX_CANDLES)
+        .await?;
+    Ok(CandleVec(out))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuctionQuery {
+    pub stock_id: String,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_auction(
+    AuthUser(_user): AuthUser,
+    State(state): State<AppState>,
+    Query(query): Query<AuctionQuery>,
+) -> Result<AuctionIndicative, AppError> {
+    let stock_id = query
+        .stock_id
+        .parse()
+        .map_err(|_| AppError::StockNotFound)?;
+
+    state.engine.auction_indicative(stock_id).await
 }
@@ -0,0 +1,25 @@
+-- Order sides and types, and trade prices, for databases created before
+-- they were added to init.sql. Orders from before only had a price if they
+-- were sells, which rested as limit orders, while buys were market orders.
+-- Trades went through at their sell order's price, setup transfers from
+-- addStockToUser at 0. Safe to run more than once:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0001_order_types.sql
+BEGIN;
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS is_buy BOOLEAN;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS order_type BIGINT;
+UPDATE orders SET is_buy = limit_price IS NULL WHERE is_buy IS NULL;
+-- Market (0) for buys, limit (1) for sells
+UPDATE orders SET order_type = CASE WHEN is_buy THEN 0 ELSE 1 END WHERE order_type IS NULL;
+ALTER TABLE orders
+    ALTER COLUMN is_buy SET NOT NULL,
+    ALTER COLUMN order_type SET NOT NULL;
+
+ALTER TABLE trades ADD COLUMN IF NOT EXISTS price BIGINT;
+UPDATE trades t SET price = o.limit_price
+FROM orders o
+WHERE o.order_id = t.sell_order AND t.price IS NULL;
+ALTER TABLE trades ALTER COLUMN price SET NOT NULL;
+
+COMMIT;
@@ -0,0 +1,17 @@
+-- Time in force and expiry, for databases created before they were added to
+-- init.sql. Limit orders from before rested until cancelled, market orders
+-- never rested. Run after 0001_order_types.sql, safe to run more than once:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0002_time_in_force.sql
+BEGIN;
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS time_in_force BIGINT;
+-- GTC (0) for limit orders, IOC (1) for market orders
+UPDATE orders SET time_in_force = CASE WHEN order_type = 0 THEN 1 ELSE 0 END WHERE time_in_force IS NULL;
+ALTER TABLE orders ALTER COLUMN time_in_force SET NOT NULL;
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS expiry_reason BIGINT;
+CREATE INDEX IF NOT EXISTS idx_orders_expires_at ON orders(expires_at) WHERE expires_at IS NOT NULL;
+
+COMMIT;
@@ -0,0 +1,11 @@
+-- Stop triggers, for databases created before they were added to init.sql.
+-- Run after 0002_time_in_force.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0003_stop_orders.sql
+BEGIN;
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS trigger_price BIGINT;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS triggered_at TIMESTAMP;
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS parent_order BIGINT REFERENCES orders(order_id);
+
+COMMIT;
@@ -0,0 +1,27 @@
+-- Time priority and order amendments, for databases created before they
+-- were added to init.sql. Resting orders keep the time priority they were
+-- placed with. Run after 0003_stop_orders.sql, safe to run more than once:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0004_order_amendments.sql
+BEGIN;
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS queued_at TIMESTAMP;
+UPDATE orders SET queued_at = created_at WHERE queued_at IS NULL;
+ALTER TABLE orders
+    ALTER COLUMN queued_at SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN queued_at SET NOT NULL;
+
+CREATE TABLE IF NOT EXISTS order_amendments (
+    amendment_id BIGSERIAL PRIMARY KEY,
+    order_id BIGINT NOT NULL,
+    previous_amount BIGINT NOT NULL,
+    previous_limit_price BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    limit_price BIGINT NOT NULL,
+    kept_priority BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (order_id) REFERENCES orders(order_id)
+);
+CREATE INDEX IF NOT EXISTS idx_order_amendments_order_id ON order_amendments(order_id);
+
+COMMIT;
@@ -0,0 +1,38 @@
+-- The per stock trade tape, for databases created before it was added to
+-- init.sql. Setup transfers from addStockToUser stay off the tape. Run
+-- after 0004_order_amendments.sql, safe to run more than once:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0005_trade_tape.sql
+BEGIN;
+
+ALTER TABLE trades ADD COLUMN IF NOT EXISTS stock_id BIGINT REFERENCES stocks(stock_id);
+UPDATE trades t SET stock_id = o.stock_id
+FROM orders o
+WHERE o.order_id = t.sell_order AND t.stock_id IS NULL;
+ALTER TABLE trades ALTER COLUMN stock_id SET NOT NULL;
+
+ALTER TABLE trades ADD COLUMN IF NOT EXISTS seq BIGINT;
+ALTER TABLE trades ADD COLUMN IF NOT EXISTS aggressor_is_buy BOOLEAN;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS last_trade_seq BIGINT NOT NULL DEFAULT 0;
+
+-- Number the trades of stocks without a tape yet in time order, the later
+-- of the two orders having taken liquidity
+WITH n AS (
+    SELECT t.trade_id,
+        ROW_NUMBER() OVER (PARTITION BY t.stock_id ORDER BY t.created_at, t.trade_id) AS seq,
+        ob.created_at > os.created_at AS aggressor_is_buy
+    FROM trades t
+    JOIN stocks s ON s.stock_id = t.stock_id
+    JOIN orders ob ON ob.order_id = t.buy_order
+    JOIN orders os ON os.order_id = t.sell_order
+    WHERE s.last_trade_seq = 0 AND t.seq IS NULL AND t.created_at != TIMESTAMP '0001-01-01 00:00:00'
+)
+UPDATE trades t SET seq = n.seq, aggressor_is_buy = n.aggressor_is_buy
+FROM n
+WHERE t.trade_id = n.trade_id;
+UPDATE stocks s SET last_trade_seq = m.seq
+FROM (SELECT stock_id, MAX(seq) AS seq FROM trades WHERE seq IS NOT NULL GROUP BY stock_id) m
+WHERE m.stock_id = s.stock_id AND s.last_trade_seq = 0;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_trades_stock_seq ON trades(stock_id, seq);
+
+COMMIT;
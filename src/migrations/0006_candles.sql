@@ -0,0 +1,31 @@
+-- Candles, for databases created before they were added to init.sql. Rolls
+-- the existing tape up into bars. Run after 0005_trade_tape.sql, safe to
+-- run more than once:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0006_candles.sql
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS candles (
+    stock_id BIGINT NOT NULL,
+    interval_secs BIGINT NOT NULL,
+    bucket TIMESTAMP NOT NULL,
+    open BIGINT NOT NULL,
+    high BIGINT NOT NULL,
+    low BIGINT NOT NULL,
+    close BIGINT NOT NULL,
+    volume BIGINT NOT NULL,
+    PRIMARY KEY (stock_id, interval_secs, bucket),
+    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id)
+);
+
+-- Roll the tape up into 1m, 5m, 1h and 1d bars, leaving any bar already
+-- there alone
+INSERT INTO candles (stock_id, interval_secs, bucket, open, high, low, close, volume)
+SELECT t.stock_id, i.secs, date_bin(make_interval(secs => i.secs), t.created_at, TIMESTAMP '2000-01-01') AS bucket,
+    (array_agg(t.price ORDER BY t.seq))[1], MAX(t.price), MIN(t.price), (array_agg(t.price ORDER BY t.seq DESC))[1], SUM(t.amount)
+FROM trades t CROSS JOIN unnest(ARRAY[60, 300, 3600, 86400]) AS i(secs)
+WHERE t.seq IS NOT NULL
+GROUP BY t.stock_id, i.secs, bucket
+ON CONFLICT (stock_id, interval_secs, bucket) DO NOTHING;
+
+COMMIT;
@@ -0,0 +1,11 @@
+-- Self-trade prevention, for databases created before it was added to
+-- init.sql. Existing orders cancel the newest order. Run after
+-- 0006_candles.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0007_self_trade_prevention.sql
+BEGIN;
+
+-- Cancel newest (0)
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS self_trade_prevention BIGINT NOT NULL DEFAULT 0;
+
+COMMIT;
@@ -0,0 +1,9 @@
+-- Iceberg orders, for databases created before they were added to
+-- init.sql. Run after 0007_self_trade_prevention.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0008_iceberg_orders.sql
+BEGIN;
+
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS display_quantity BIGINT;
+
+COMMIT;
@@ -0,0 +1,17 @@
+-- OCO and bracket groups, for databases created before they were added to
+-- init.sql. Run after 0008_iceberg_orders.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0009_order_groups.sql
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS order_groups (
+    group_id BIGSERIAL PRIMARY KEY,
+    group_type BIGINT NOT NULL,
+    take_profit_price BIGINT,
+    stop_loss_price BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS group_id BIGINT REFERENCES order_groups(group_id);
+CREATE INDEX IF NOT EXISTS idx_orders_group_id ON orders(group_id) WHERE group_id IS NOT NULL;
+
+COMMIT;
@@ -0,0 +1,20 @@
+-- Trading phases and auctions, for databases created before they were
+-- added to init.sql. Every stock stays in continuous trading. Run after
+-- 0009_order_groups.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0010_auctions.sql
+BEGIN;
+
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS trading_phase BIGINT NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS auctions (
+    auction_id BIGSERIAL PRIMARY KEY,
+    stock_id BIGINT NOT NULL,
+    phase BIGINT NOT NULL,
+    price BIGINT NOT NULL,
+    volume BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (stock_id) REFERENCES stocks(stock_id)
+);
+CREATE INDEX IF NOT EXISTS idx_auctions_stock_id ON auctions(stock_id);
+
+COMMIT;
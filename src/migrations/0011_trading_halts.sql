@@ -0,0 +1,14 @@
+-- Halts and circuit breakers, for databases created before they were added
+-- to init.sql. No stock is halted and every breaker is off. Run after
+-- 0010_auctions.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0011_trading_halts.sql
+BEGIN;
+
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS halted BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS halted_until TIMESTAMP;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS breaker_move_percent BIGINT;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS breaker_window_secs BIGINT;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS breaker_halt_secs BIGINT;
+
+COMMIT;
@@ -0,0 +1,13 @@
+-- Tick sizes, lot sizes and price collars, for databases created before
+-- they were added to init.sql. Every stock starts with no limits. Run after
+-- 0011_trading_halts.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0012_trading_rules.sql
+BEGIN;
+
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS tick_size BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS lot_size BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS max_order_quantity BIGINT;
+ALTER TABLE stocks ADD COLUMN IF NOT EXISTS price_collar_percent BIGINT;
+
+COMMIT;
@@ -0,0 +1,33 @@
+-- Fee tiers and the fees charged on trades, for databases created before
+-- they were added to init.sql. Every user starts on the free 'standard'
+-- tier. Run after 0012_trading_rules.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0013_fees.sql
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS fee_schedules (
+    tier TEXT PRIMARY KEY,
+    maker_bps BIGINT NOT NULL,
+    taker_bps BIGINT NOT NULL,
+    min_fee BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO fee_schedules (tier, maker_bps, taker_bps) VALUES ('standard', 0, 0)
+ON CONFLICT (tier) DO NOTHING;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS fee_tier TEXT NOT NULL DEFAULT 'standard' REFERENCES fee_schedules(tier);
+
+CREATE TABLE IF NOT EXISTS fees (
+    fee_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
+    trade_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    order_id BIGINT NOT NULL,
+    is_maker BOOLEAN NOT NULL,
+    amount BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (trade_id) REFERENCES trades(trade_id),
+    FOREIGN KEY (user_id) REFERENCES users(user_id),
+    FOREIGN KEY (order_id) REFERENCES orders(order_id)
+);
+CREATE INDEX IF NOT EXISTS idx_fees_user_id ON fees(user_id);
+
+COMMIT;
@@ -0,0 +1,121 @@
+-- Cash ledger for databases created before it was added to init.sql.
+-- Journals every deposit, trade and fee that isn't already, then sets each
+-- user account's balance from its postings. Run after 0013_fees.sql, safe
+-- to run more than once:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0014_ledger.sql
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS ledger_accounts (
+    account_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT UNIQUE,
+    account_type BIGINT NOT NULL,
+    balance BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_ledger_accounts_exchange ON ledger_accounts(account_type) WHERE user_id IS NULL;
+
+CREATE TABLE IF NOT EXISTS journal_entries (
+    entry_id BIGSERIAL PRIMARY KEY,
+    entry_type BIGINT NOT NULL,
+    deposit_id BIGINT UNIQUE,
+    trade_id BIGINT UNIQUE,
+    fee_id BIGINT UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (deposit_id) REFERENCES deposits(deposit_id),
+    FOREIGN KEY (trade_id) REFERENCES trades(trade_id),
+    FOREIGN KEY (fee_id) REFERENCES fees(fee_id)
+);
+
+CREATE TABLE IF NOT EXISTS postings (
+    posting_id BIGSERIAL PRIMARY KEY,
+    entry_id BIGINT NOT NULL,
+    account_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    FOREIGN KEY (entry_id) REFERENCES journal_entries(entry_id),
+    FOREIGN KEY (account_id) REFERENCES ledger_accounts(account_id)
+);
+CREATE INDEX IF NOT EXISTS idx_postings_entry_id ON postings(entry_id);
+CREATE INDEX IF NOT EXISTS idx_postings_account_id ON postings(account_id);
+
+-- Keep the balances still while they're rebuilt
+LOCK TABLE ledger_accounts IN EXCLUSIVE MODE;
+
+-- Funding (1) and fee revenue (2) accounts, and a cash account (0) per user
+INSERT INTO ledger_accounts (account_type) VALUES (1), (2)
+ON CONFLICT (account_type) WHERE user_id IS NULL DO NOTHING;
+INSERT INTO ledger_accounts (user_id, account_type)
+SELECT user_id, 0 FROM users
+ON CONFLICT (user_id) DO NOTHING;
+
+-- Deposits, from the funding account to the user
+WITH d AS (
+    SELECT d.deposit_id, d.amount, d.created_at, a.account_id
+    FROM deposits d
+    JOIN ledger_accounts a ON a.user_id = d.user_id
+    WHERE NOT EXISTS (SELECT 1 FROM journal_entries e WHERE e.deposit_id = d.deposit_id)
+),
+e AS (
+    INSERT INTO journal_entries (entry_type, deposit_id, created_at)
+    SELECT 0, deposit_id, created_at FROM d
+    RETURNING entry_id, deposit_id
+)
+INSERT INTO postings (entry_id, account_id, amount)
+SELECT e.entry_id, x.account_id, x.amount
+FROM e
+JOIN d ON d.deposit_id = e.deposit_id
+CROSS JOIN LATERAL (VALUES
+    (d.account_id, d.amount),
+    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id IS NULL AND a.account_type = 1), -d.amount)
+) AS x(account_id, amount);
+
+-- Trades on the tape, from the buyer to the seller. Setup transfers have no
+-- sequence and no price
+WITH t AS (
+    SELECT t.trade_id, t.amount * t.price AS notional, t.created_at, ab.account_id AS buyer, asl.account_id AS seller
+    FROM trades t
+    JOIN orders ob ON ob.order_id = t.buy_order
+    JOIN orders os ON os.order_id = t.sell_order
+    JOIN ledger_accounts ab ON ab.user_id = ob.user_id
+    JOIN ledger_accounts asl ON asl.user_id = os.user_id
+    WHERE t.seq IS NOT NULL
+        AND NOT EXISTS (SELECT 1 FROM journal_entries e WHERE e.trade_id = t.trade_id)
+),
+e AS (
+    INSERT INTO journal_entries (entry_type, trade_id, created_at)
+    SELECT 1, trade_id, created_at FROM t
+    RETURNING entry_id, trade_id
+)
+INSERT INTO postings (entry_id, account_id, amount)
+SELECT e.entry_id, x.account_id, x.amount
+FROM e
+JOIN t ON t.trade_id = e.trade_id
+CROSS JOIN LATERAL (VALUES (t.buyer, -t.notional), (t.seller, t.notional)) AS x(account_id, amount);
+
+-- Fees, from the user to the fee revenue account
+WITH f AS (
+    SELECT f.fee_id, f.amount, f.created_at, a.account_id
+    FROM fees f
+    JOIN ledger_accounts a ON a.user_id = f.user_id
+    WHERE NOT EXISTS (SELECT 1 FROM journal_entries e WHERE e.fee_id = f.fee_id)
+),
+e AS (
+    INSERT INTO journal_entries (entry_type, fee_id, created_at)
+    SELECT 2, fee_id, created_at FROM f
+    RETURNING entry_id, fee_id
+)
+INSERT INTO postings (entry_id, account_id, amount)
+SELECT e.entry_id, x.account_id, x.amount
+FROM e
+JOIN f ON f.fee_id = e.fee_id
+CROSS JOIN LATERAL (VALUES
+    (f.account_id, -f.amount),
+    ((SELECT a.account_id FROM ledger_accounts a WHERE a.user_id IS NULL AND a.account_type = 2), f.amount)
+) AS x(account_id, amount);
+
+UPDATE ledger_accounts a
+SET balance = (SELECT COALESCE(SUM(p.amount), 0) FROM postings p WHERE p.account_id = a.account_id)
+WHERE a.user_id IS NOT NULL;
+
+COMMIT;
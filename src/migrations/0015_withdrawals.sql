@@ -0,0 +1,20 @@
+-- Withdrawals, for databases created before they were added to init.sql.
+-- Run after 0014_ledger.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0015_withdrawals.sql
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS withdrawals (
+    withdrawal_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
+    user_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    status BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    decided_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+CREATE INDEX IF NOT EXISTS idx_withdrawals_user_id ON withdrawals(user_id);
+
+ALTER TABLE journal_entries ADD COLUMN IF NOT EXISTS withdrawal_id BIGINT UNIQUE REFERENCES withdrawals(withdrawal_id);
+
+COMMIT;
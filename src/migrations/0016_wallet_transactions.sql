@@ -0,0 +1,49 @@
+-- Wallet adjustments, and deposits numbered along with the other wallet
+-- transactions, for databases created before they were in init.sql. Run
+-- after 0015_withdrawals.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0016_wallet_transactions.sql
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS adjustments (
+    adjustment_id BIGINT PRIMARY KEY DEFAULT nextval('trades_trade_id_seq'),
+    user_id BIGINT NOT NULL,
+    amount BIGINT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(user_id)
+);
+CREATE INDEX IF NOT EXISTS idx_adjustments_user_id ON adjustments(user_id);
+
+ALTER TABLE journal_entries ADD COLUMN IF NOT EXISTS adjustment_id BIGINT UNIQUE REFERENCES adjustments(adjustment_id);
+
+INSERT INTO ledger_accounts (account_type) VALUES (3)
+ON CONFLICT (account_type) WHERE user_id IS NULL DO NOTHING;
+
+-- Deposit ids had their own sequence and so could clash with the ids of
+-- trades. Renumber them from the shared one, past every existing deposit id
+-- so the new ids never collide with the old ones
+DO $$
+BEGIN
+    IF pg_get_serial_sequence('deposits', 'deposit_id') IS NOT NULL THEN
+        PERFORM setval('trades_trade_id_seq', GREATEST(
+            (SELECT last_value FROM trades_trade_id_seq),
+            (SELECT COALESCE(MAX(deposit_id), 0) FROM deposits)
+        ));
+
+        CREATE TEMPORARY TABLE deposit_ids ON COMMIT DROP AS
+        SELECT deposit_id AS old_id, nextval('trades_trade_id_seq') AS new_id
+        FROM (SELECT deposit_id FROM deposits ORDER BY deposit_id) d;
+
+        ALTER TABLE journal_entries DROP CONSTRAINT IF EXISTS journal_entries_deposit_id_fkey;
+        UPDATE deposits d SET deposit_id = m.new_id FROM deposit_ids m WHERE d.deposit_id = m.old_id;
+        UPDATE journal_entries e SET deposit_id = m.new_id FROM deposit_ids m WHERE e.deposit_id = m.old_id;
+        ALTER TABLE journal_entries ADD CONSTRAINT journal_entries_deposit_id_fkey FOREIGN KEY (deposit_id) REFERENCES deposits(deposit_id);
+
+        ALTER TABLE deposits ALTER COLUMN deposit_id SET DEFAULT nextval('trades_trade_id_seq');
+        DROP SEQUENCE deposits_deposit_id_seq;
+    END IF;
+END
+$$;
+
+COMMIT;
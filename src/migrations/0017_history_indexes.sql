@@ -0,0 +1,19 @@
+-- Indexes for paging and filtering users' stock and wallet transactions, for
+-- databases created before they were in init.sql. Run after
+-- 0016_wallet_transactions.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0017_history_indexes.sql
+BEGIN;
+
+CREATE INDEX IF NOT EXISTS idx_orders_user_id_created_at ON orders(user_id, created_at, order_id);
+CREATE INDEX IF NOT EXISTS idx_orders_user_id_stock_id ON orders(user_id, stock_id);
+-- Covered by the one above
+DROP INDEX IF EXISTS idx_orders_user_id;
+
+-- Replace the fill lookups with ones in time order
+DROP INDEX IF EXISTS idx_sell_order;
+DROP INDEX IF EXISTS idx_buy_order;
+CREATE INDEX idx_sell_order ON trades(sell_order, created_at, trade_id);
+CREATE INDEX idx_buy_order ON trades(buy_order, created_at, trade_id);
+
+COMMIT;
@@ -0,0 +1,10 @@
+-- The exchange's own ledger accounts no longer keep a running balance, it's
+-- summed from their postings instead. Run after 0017_history_indexes.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0018_house_balances.sql
+BEGIN;
+
+ALTER TABLE ledger_accounts ALTER COLUMN balance DROP NOT NULL;
+UPDATE ledger_accounts SET balance = NULL WHERE user_id IS NULL;
+
+COMMIT;
@@ -0,0 +1,10 @@
+-- Admin users, for databases created before they were added to init.sql.
+-- The seeded admin user is the only one. Run after 0018_house_balances.sql:
+--
+--   psql "$DB_ENDPOINT" -f src/migrations/0019_admins.sql
+BEGIN;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE users SET is_admin = TRUE WHERE user_name = 'admin';
+
+COMMIT;
@@ -1,18 +1,70 @@
 use axum::extract::{Json, State};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     auth::AuthUser,
-    types::{AppError, AppState, EmptyCreatedResponse, EmptyResponse, OrderType},
+    engine::NewOrder,
+    types::{
+        AppError, AppState, EmptyCreatedResponse, EmptyResponse, OrderType, SelfTradePrevention,
+        TimeInForce,
+    },
 };
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct PlaceStockOrderRequest {
     pub stock_id: String,
     pub is_buy: bool,
     pub order_type: OrderType,
     pub quantity: i64,
     pub price: Option<i64>,
+    /// Defaults to IOC for market orders and GTC for limit orders
+    #[serde(default)]
+    pub time_in_force: Option<TimeInForce>,
+    /// Required for, and only allowed on, GTD orders
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Required for, and only allowed on, stop orders
+    #[serde(default)]
+    pub trigger_price: Option<i64>,
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Makes a resting limit order an iceberg that only shows this much of
+    /// its quantity at a time
+    #[serde(default)]
+    pub display_quantity: Option<i64>,
+    /// Stop leg placed along with this limit order for the same stock and
+    /// side, covering whatever of the limit order is left open. The stop
+    /// triggering or the limit order filling completely cancels the other
+    /// leg, a partial fill shrinks the stop.
+    #[serde(default)]
+    pub one_cancels_other: Option<OrderLeg>,
+    /// Limit price of the exit placed once this order has filled, making it
+    /// the entry of a bracket along with `stop_loss`
+    #[serde(default)]
+    pub take_profit: Option<i64>,
+    /// Trigger price of the stop exit placed once this order has filled
+    #[serde(default)]
+    pub stop_loss: Option<i64>,
+}
+
+/// The stop leg of an OCO pair
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct OrderLeg {
+    pub order_type: OrderType,
+    pub price: Option<i64>,
+    pub trigger_price: Option<i64>,
+}
+
+impl OrderLeg {
+    fn is_valid(&self) -> bool {
+        let valid_price = match self.order_type {
+            OrderType::Stop => self.price.is_none(),
+            OrderType::StopLimit => self.price.is_some(),
+            OrderType::Market | OrderType::Limit => false,
+        };
+        valid_price && self.trigger_price.is_some()
+    }
 }
 
 #[tracing::instrument(skip_all)]
@@ -21,34 +73,185 @@ pub async fn place_stock_order(
     State(state): State<AppState>,
     Json(body): Json<PlaceStockOrderRequest>,
 ) -> Result<EmptyCreatedResponse, AppError> {
-    if !(body.is_buy && matches!(body.order_type, OrderType::Market) && body.price.is_none()
-        || (!body.is_buy && matches!(body.order_type, OrderType::Limit) && body.price.is_some()))
+    let valid_price = match body.order_type {
+        OrderType::Market | OrderType::Stop => body.price.is_none(),
+        OrderType::Limit | OrderType::StopLimit => body.price.is_some(),
+    } && body.trigger_price.is_some() == body.order_type.is_stop();
+    if !valid_price {
+        return Err(AppError::BadRequest);
+    }
+
+    // A stop's time in force applies to the order it turns into
+    let time_in_force = body
+        .time_in_force
+        .unwrap_or(match body.order_type.triggered() {
+            OrderType::Limit => TimeInForce::GoodTilCancelled,
+            _ => TimeInForce::ImmediateOrCancel,
+        });
+    let valid_tif = match (body.order_type.triggered(), time_in_force) {
+        (OrderType::Limit, TimeInForce::GoodTilDate) => {
+            body.expires_at.is_some_and(|at| at > Utc::now())
+        }
+        // Market orders never rest so they can't live past their arrival
+        (OrderType::Market, TimeInForce::GoodTilCancelled | TimeInForce::GoodTilDate) => false,
+        _ => body.expires_at.is_none(),
+    };
+    if !valid_tif {
+        return Err(AppError::BadRequest);
+    }
+
+    let valid_display = match body.display_quantity {
+        Some(display) => {
+            body.order_type.triggered() == OrderType::Limit
+                && matches!(
+                    time_in_force,
+                    TimeInForce::GoodTilCancelled | TimeInForce::GoodTilDate
+                )
+                && display > 0
+                && display <= body.quantity
+        }
+        None => true,
+    };
+    if !valid_display {
+        return Err(AppError::BadRequest);
+    }
+
+    let valid_group = match (&body.one_cancels_other, body.take_profit, body.stop_loss) {
+        (None, None, None) => true,
+        (Some(leg), None, None) => body.order_type == OrderType::Limit && leg.is_valid(),
+        // Take-profit above and stop-loss below a long entry, the other way
+        // around for a short one
+        (None, Some(take_profit), Some(stop_loss)) => {
+            let (low, high) = if body.is_buy {
+                (stop_loss, take_profit)
+            } else {
+                (take_profit, stop_loss)
+            };
+            !body.order_type.is_stop()
+                && low > 0
+                && body
+                    .price
+                    .map_or(low < high, |price| low < price && price < high)
+        }
+        _ => false,
+    };
+    if !valid_group {
+        return Err(AppError::BadRequest);
+    }
+
+    let stock_id = body.stock_id.parse().map_err(|_| AppError::StockNotFound)?;
+    let rules = state.db.get_trading_rules(stock_id).await?;
+    rules.check_quantity(body.quantity)?;
+    if let Some(display) = body.display_quantity {
+        rules.check_quantity(display)?;
+    }
+    let leg = body.one_cancels_other.as_ref();
+    for price in [
+        body.price,
+        body.trigger_price,
+        leg.and_then(|l| l.price),
+        leg.and_then(|l| l.trigger_price),
+        body.take_profit,
+        body.stop_loss,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        rules.check_price(price)?;
+    }
+    // Only a limit order can trade at its price on arrival, stop limits
+    // wait for the market to get there
+    if body.order_type == OrderType::Limit
+        && let Some(price) = body.price
+        && rules.price_collar_percent.is_some()
     {
+        rules.check_collar(price, state.db.get_last_price(stock_id).await?)?;
+    }
+
+    let order = NewOrder {
+        user_id: user,
+        stock_id,
+        is_buy: body.is_buy,
+        order_type: body.order_type,
+        quantity: body.quantity,
+        price: body.price,
+        time_in_force,
+        expires_at: body.expires_at,
+        trigger_price: body.trigger_price,
+        parent_order: None,
+        self_trade_prevention: body.self_trade_prevention,
+        display_quantity: body.display_quantity,
+        group_id: None,
+    };
+    match (body.one_cancels_other, body.take_profit, body.stop_loss) {
+        (Some(leg), _, _) => {
+            let stop = NewOrder {
+                order_type: leg.order_type,
+                price: leg.price,
+                time_in_force: match leg.order_type.triggered() {
+                    OrderType::Limit => TimeInForce::GoodTilCancelled,
+                    _ => TimeInForce::ImmediateOrCancel,
+                },
+                expires_at: None,
+                trigger_price: leg.trigger_price,
+                display_quantity: None,
+                ..order.clone()
+            };
+            state.engine.place_oco(order, stop).await?;
+        }
+        (None, Some(take_profit), Some(stop_loss)) => {
+            state
+                .engine
+                .place_bracket(order, take_profit, stop_loss)
+                .await?;
+        }
+        _ => state.engine.place_order(order).await?,
+    }
+
+    Ok(EmptyCreatedResponse {})
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct ModifyStockOrderRequest {
+    pub stock_tx_id: String,
+    /// New total quantity, including whatever has already filled
+    pub quantity: Option<i64>,
+    pub price: Option<i64>,
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn modify_stock_order(
+    AuthUser(user): AuthUser,
+    State(state): State<AppState>,
+    Json(body): Json<ModifyStockOrderRequest>,
+) -> Result<EmptyResponse, AppError> {
+    if body.quantity.is_none() && body.price.is_none() {
         return Err(AppError::BadRequest);
     }
-    if !body.is_buy {
-        state
-            .db
-            .create_sell_order(
-                user,
-                body.stock_id.parse().map_err(|_| AppError::StockNotFound)?,
-                body.quantity,
-                body.price.expect("is a sell order"),
-            )
-            .await?;
-        return Ok(EmptyCreatedResponse {});
+    let stock_tx_id = body
+        .stock_tx_id
+        .parse()
+        .map_err(|_| AppError::StockTransactionNotFound)?;
+
+    // Ownership first, so someone else's order is never checked against
+    // the rules of its stock
+    let stock_id = state.db.get_order_stock(Some(user), stock_tx_id).await?;
+    let rules = state.db.get_trading_rules(stock_id).await?;
+    if let Some(quantity) = body.quantity {
+        rules.check_quantity(quantity)?;
+    }
+    if let Some(price) = body.price {
+        rules.check_price(price)?;
+        if rules.price_collar_percent.is_some() {
+            rules.check_collar(price, state.db.get_last_price(stock_id).await?)?;
+        }
     }
 
     state
-        .db
-        .create_buy_order(
-            user,
-            body.stock_id.parse().map_err(|_| AppError::StockNotFound)?,
-            body.quantity,
-        )
+        .engine
+        .modify_order(user, stock_tx_id, body.quantity, body.price)
         .await?;
-
-    Ok(EmptyCreatedResponse {})
+    Ok(EmptyResponse {})
 }
 
 #[allow(unused)]
@@ -64,8 +267,8 @@ pub async fn cancel_stock_transaction(
     Json(body): Json<CancelStockTransactionRequest>,
 ) -> Result<EmptyResponse, AppError> {
     state
-        .db
-        .cancel_sell_order(
+        .engine
+        .cancel_order(
             user,
             body.stock_tx_id
                 .parse()
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::LotMethod;
+
+/// One side of a trade a user took part in, fees included
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub stock_id: i64,
+    pub is_buy: bool,
+    pub quantity: i64,
+    pub price: i64,
+    pub fee: i64,
+    /// Shares handed over by `add_stock_to_user` rather than bought
+    pub seeded: bool,
+}
+
+/// Shares of a stock a user holds and what they paid for them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Position {
+    pub quantity: i64,
+    /// Part of `quantity` that was seeded rather than bought
+    pub seeded: i64,
+    /// Cost of the lots still held, buy fees included
+    pub cost_basis: i64,
+    /// Sale proceeds net of sell fees, less the cost of the lots sold.
+    /// Seeded shares have no cost, so their sales are left out
+    pub realized_pnl: i64,
+}
+
+/// Replays a user's fills, oldest first, into a position per stock. Each buy
+/// opens a lot costing its notional plus fee, each sell closes lots picked by
+/// `method`. [`LotMethod::Average`] keeps a single lot that every buy is
+/// pooled into. Seeded shares are kept in lots of their own, and a sell's fee
+/// is split between the seeded and bought shares it closes, rounded down for
+/// the seeded ones.
+pub fn replay(fills: &[Fill], method: LotMethod) -> HashMap<i64, Position> {
+    let mut stocks: HashMap<i64, (Position, VecDeque<Lot>)> = HashMap::new();
+
+    for fill in fills {
+        let (position, lots) = stocks.entry(fill.stock_id).or_default();
+        if fill.is_buy {
+            let lot = Lot {
+                quantity: fill.quantity,
+                cost: fill.quantity * fill.price + fill.fee,
+                seeded: fill.seeded,
+            };
+            match (method, lots.back_mut()) {
+                (LotMethod::Average, Some(pooled)) if pooled.seeded == lot.seeded => {
+                    pooled.quantity += lot.quantity;
+                    pooled.cost += lot.cost;
+                }
+                _ => lots.push_back(lot),
+            }
+            position.quantity += fill.quantity;
+            if fill.seeded {
+                position.seeded += fill.quantity;
+            }
+            position.cost_basis += lot.cost;
+            continue;
+        }
+
+        let mut to_close = fill.quantity;
+        let mut closed_cost = 0;
+        let mut closed_seeded = 0;
+        while to_close > 0 {
+            let lot = match method {
+                LotMethod::Fifo | LotMethod::Average => lots.front_mut(),
+                LotMethod::Lifo => lots.back_mut(),
+            };
+            // Selling more than was ever bought shouldn't happen, those
+            // shares are closed at no cost
+            let Some(lot) = lot else { break };
+            let quantity = to_close.min(lot.quantity);
+            let cost = lot.take(quantity);
+            closed_cost += cost;
+            if lot.seeded {
+                closed_seeded += quantity;
+            }
+            to_close -= quantity;
+            if lot.quantity == 0 {
+                match method {
+                    LotMethod::Lifo => lots.pop_back(),
+                    LotMethod::Fifo | LotMethod::Average => lots.pop_front(),
+                };
+            }
+        }
+        let seeded_fee = (fill.fee as i128 * closed_seeded as i128 / fill.quantity as i128) as i64;
+        position.quantity -= fill.quantity;
+        position.seeded -= closed_seeded;
+        position.cost_basis -= closed_cost;
+        position.realized_pnl +=
+            (fill.quantity - closed_seeded) * fill.price - (fill.fee - seeded_fee) - closed_cost;
+    }
+
+    stocks
+        .into_iter()
+        .map(|(stock_id, (position, _))| (stock_id, position))
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Lot {
+    quantity: i64,
+    cost: i64,
+    seeded: bool,
+}
+
+impl Lot {
+    /// Removes `quantity` shares and their share of the cost, rounded down so
+    /// the lot keeps the remainder
+    fn take(&mut self, quantity: i64) -> i64 {
+        let cost = (self.cost as i128 * quantity as i128 / self.quantity as i128) as i64;
+        self.quantity -= quantity;
+        self.cost -= cost;
+        cost
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{Fill, Position, replay};
+    use crate::types::LotMethod;
+
+    fn fill(is_buy: bool, quantity: i64, price: i64, fee: i64) -> Fill {
+        Fill {
+            stock_id: 1,
+            is_buy,
+            quantity,
+            price,
+            fee,
+            seeded: false,
+        }
+    }
+
+    #[test]
+    fn test_lot_methods() {
+        let fills = [
+            fill(true, 10, 100, 5),
+            fill(true, 10, 120, 5),
+            fill(false, 15, 130, 4),
+        ];
+        let position = |method| replay(&fills, method).remove(&1).unwrap();
+
+        // First lot and half the second are sold
+        assert_eq!(
+            position(LotMethod::Fifo),
+            Position {
+                quantity: 5,
+                seeded: 0,
+                cost_basis: 603,
+                realized_pnl: 1950 - 4 - (1005 + 602)
+            }
+        );
+        // Second lot and half the first are sold
+        assert_eq!(
+            position(LotMethod::Lifo),
+            Position {
+                quantity: 5,
+                seeded: 0,
+                cost_basis: 503,
+                realized_pnl: 1950 - 4 - (1205 + 502)
+            }
+        );
+        // Three quarters of the pooled 2210 are sold
+        assert_eq!(
+            position(LotMethod::Average),
+            Position {
+                quantity: 5,
+                seeded: 0,
+                cost_basis: 553,
+                realized_pnl: 1950 - 4 - 1657
+            }
+        );
+    }
+
+    #[test]
+    fn test_closed_position_keeps_realized_pnl() {
+        let fills = [
+            fill(true, 3, 10, 0),
+            fill(false, 3, 7, 1),
+            fill(true, 2, 20, 1),
+            Fill {
+                stock_id: 2,
+                ..fill(true, 1, 50, 0)
+            },
+        ];
+        let positions = replay(&fills, LotMethod::Fifo);
+
+        assert_eq!(
+            positions[&1],
+            Position {
+                quantity: 2,
+                seeded: 0,
+                cost_basis: 41,
+                realized_pnl: -10
+            }
+        );
+        assert_eq!(
+            positions[&2],
+            Position {
+                quantity: 1,
+                seeded: 0,
+                cost_basis: 50,
+                realized_pnl: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_seeded_shares_are_left_out_of_pnl() {
+        let fills = [
+            Fill {
+                seeded: true,
+                ..fill(true, 10, 0, 0)
+            },
+            fill(true, 10, 100, 10),
+            fill(false, 15, 120, 30),
+        ];
+        let position = |method| replay(&fills, method).remove(&1).unwrap();
+
+        // The seeded lot is older, so it goes first. 10 of the 15 shares sold
+        // were seeded, leaving a third of the fee
+        assert_eq!(
+            position(LotMethod::Fifo),
+            Position {
+                quantity: 5,
+                seeded: 0,
+                cost_basis: 505,
+                realized_pnl: 5 * 120 - 10 - 505
+            }
+        );
+        assert_eq!(
+            position(LotMethod::Average),
+            Position {
+                quantity: 5,
+                seeded: 0,
+                cost_basis: 505,
+                realized_pnl: 5 * 120 - 10 - 505
+            }
+        );
+        // The bought lot goes first, then half the seeded one
+        assert_eq!(
+            position(LotMethod::Lifo),
+            Position {
+                quantity: 5,
+                seeded: 5,
+                cost_basis: 0,
+                realized_pnl: 10 * 120 - 20 - 1010
+            }
+        );
+    }
+}
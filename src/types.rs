@@ -7,11 +7,12 @@ use fake::{Dummy, faker::company::en::CompanyName};
 use serde::{Deserialize, Serialize};
 use serde_json::{self, json};
 
-use crate::DB;
+use crate::{DB, engine::Engine};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DB,
+    pub engine: Engine,
 }
 
 #[derive(Serialize, Deserialize, Debug, Dummy, PartialEq)]
@@ -19,8 +20,22 @@ pub struct StockPrice {
     pub stock_id: String,
     #[dummy(faker = "CompanyName()")]
     pub stock_name: String,
+    /// Lowest open ask, or 0 without one. Kept for older clients, prefer
+    /// `last_price` and `best_ask`
     #[dummy(faker = "1..200")]
     pub current_price: i64,
+    pub last_price: Option<i64>,
+    pub best_bid: Option<i64>,
+    pub best_ask: Option<i64>,
+    /// Last price before the current (UTC) day, the official close when a
+    /// closing auction set it
+    pub previous_close: Option<i64>,
+    /// `last_price - previous_close`
+    pub day_change: Option<i64>,
+    #[dummy(faker = "0..10000")]
+    pub volume_24h: i64,
+    /// Halted by an admin or by its circuit breaker
+    pub trading_halted: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Dummy, PartialEq)]
@@ -30,12 +45,43 @@ pub struct StockPortfolio {
     pub stock_name: String,
     #[dummy(faker = "1..1000")]
     pub quantity_owned: i64,
+    /// `cost_basis` per bought share held, rounded down. `None` when every
+    /// share held was seeded by `addStockToUser`
+    pub average_cost: Option<i64>,
+    /// Cost of the shares held, buy fees included. Covers shares offered in
+    /// open sell orders too, as does `market_value`
+    pub cost_basis: i64,
+    /// Shares held at the last trade price, `None` until the stock trades
+    pub market_value: Option<i64>,
+    /// `market_value` of the bought shares less `cost_basis`. Shares seeded
+    /// by `addStockToUser` have no cost and are left out of both profit
+    /// figures
+    pub unrealized_pnl: Option<i64>,
+    /// Proceeds of the bought shares sold net of their sell fees, less their
+    /// cost
+    pub realized_pnl: i64,
+}
+
+/// How shares sold are matched to the buys they came from when working out
+/// cost basis
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LotMethod {
+    /// Oldest shares are sold first
+    #[default]
+    Fifo,
+    /// Newest shares are sold first
+    Lifo,
+    /// Every share costs the average of all held
+    Average,
 }
 
 #[derive(Serialize, Deserialize, Debug, Dummy)]
 pub struct WalletTransaction {
     pub wallet_tx_id: String,
-    pub stock_tx_id: String,
+    pub kind: EntryType,
+    /// The user's order a trade or fee belongs to
+    pub stock_tx_id: Option<String>,
     pub is_debit: bool,
     #[dummy(faker = "1..10000")]
     pub amount: i64,
@@ -45,6 +91,7 @@ pub struct WalletTransaction {
 #[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderStatus {
+    Expired = -3,
     Failed = -2,
     Cancelled = -1,
     Completed = 0,
@@ -55,6 +102,7 @@ pub enum OrderStatus {
 impl From<i64> for OrderStatus {
     fn from(value: i64) -> Self {
         match value {
+            -3 => OrderStatus::Expired,
             -2 => OrderStatus::Failed,
             -1 => OrderStatus::Cancelled,
             0 => OrderStatus::Completed,
@@ -65,11 +113,196 @@ impl From<i64> for OrderStatus {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy, Default)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum OrderType {
-    Market,
-    Limit,
+    #[default]
+    Market = 0,
+    Limit = 1,
+    /// Dormant until a trade prints at or through its trigger, then a market
+    /// order
+    Stop = 2,
+    /// Dormant until a trade prints at or through its trigger, then a limit
+    /// order
+    #[serde(rename = "STOP_LIMIT")]
+    StopLimit = 3,
+}
+
+impl OrderType {
+    pub fn is_stop(self) -> bool {
+        matches!(self, OrderType::Stop | OrderType::StopLimit)
+    }
+
+    /// The order type a stop turns into once triggered
+    pub fn triggered(self) -> Self {
+        match self {
+            OrderType::Stop => OrderType::Market,
+            OrderType::StopLimit => OrderType::Limit,
+            other => other,
+        }
+    }
+}
+
+impl From<i64> for OrderType {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => OrderType::Market,
+            1 => OrderType::Limit,
+            2 => OrderType::Stop,
+            3 => OrderType::StopLimit,
+            _ => unreachable!("Invalid i64 value for OrderType"),
+        }
+    }
+}
+
+/// How long an order stays on the book
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy)]
+pub enum TimeInForce {
+    /// Rests until filled or cancelled
+    #[serde(rename = "GTC")]
+    GoodTilCancelled = 0,
+    /// Fills what it can immediately, the rest is cancelled
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel = 1,
+    /// Fills completely immediately or not at all
+    #[serde(rename = "FOK")]
+    FillOrKill = 2,
+    /// Rests until filled, cancelled or its `expires_at` passes
+    #[serde(rename = "GTD")]
+    GoodTilDate = 3,
+}
+
+impl From<i64> for TimeInForce {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => TimeInForce::GoodTilCancelled,
+            1 => TimeInForce::ImmediateOrCancel,
+            2 => TimeInForce::FillOrKill,
+            3 => TimeInForce::GoodTilDate,
+            _ => unreachable!("Invalid i64 value for TimeInForce"),
+        }
+    }
+}
+
+/// Why (part of) an order was taken off the book without filling
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExpiryReason {
+    /// Market and IOC orders cancel whatever doesn't fill on arrival
+    NotFilledImmediately = 0,
+    /// FOK order the book couldn't fill in full
+    NotFullyFillable = 1,
+    /// GTD order still open at its `expires_at`
+    ExpiryTimeReached = 2,
+    /// Self-trade prevention stopped the order trading with one of the
+    /// same user's orders
+    SelfTradePrevented = 3,
+    /// Another leg of the order's OCO pair or bracket traded or triggered
+    OtherLegExecuted = 4,
+}
+
+impl From<i64> for ExpiryReason {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => ExpiryReason::NotFilledImmediately,
+            1 => ExpiryReason::NotFullyFillable,
+            2 => ExpiryReason::ExpiryTimeReached,
+            3 => ExpiryReason::SelfTradePrevented,
+            4 => ExpiryReason::OtherLegExecuted,
+            _ => unreachable!("Invalid i64 value for ExpiryReason"),
+        }
+    }
+}
+
+/// What happens when an incoming order reaches a resting order of the same
+/// user. Only the incoming order's mode applies, in an auction uncross that
+/// of the order placed last.
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SelfTradePrevention {
+    /// Cancel whatever is left of the incoming order
+    #[default]
+    CancelNewest = 0,
+    /// Cancel the resting order and keep matching
+    CancelOldest = 1,
+    /// Cancel the resting order and whatever is left of the incoming order
+    CancelBoth = 2,
+    /// Let the user trade with themselves
+    Allow = 3,
+}
+
+impl From<i64> for SelfTradePrevention {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => SelfTradePrevention::CancelNewest,
+            1 => SelfTradePrevention::CancelOldest,
+            2 => SelfTradePrevention::CancelBoth,
+            3 => SelfTradePrevention::Allow,
+            _ => unreachable!("Invalid i64 value for SelfTradePrevention"),
+        }
+    }
+}
+
+/// How the orders placed together in one request depend on each other
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderGroupType {
+    /// A limit leg and a stop leg where the first to fill or trigger cancels
+    /// the other
+    #[serde(rename = "OCO")]
+    OneCancelsOther = 0,
+    /// An entry whose take-profit and stop-loss exits are placed, as an OCO
+    /// pair, once it has filled
+    Bracket = 1,
+}
+
+impl From<i64> for OrderGroupType {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => OrderGroupType::OneCancelsOther,
+            1 => OrderGroupType::Bracket,
+            _ => unreachable!("Invalid i64 value for OrderGroupType"),
+        }
+    }
+}
+
+/// Kind of account in the cash ledger
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AccountType {
+    /// A user's wallet
+    Cash = 0,
+    /// Where deposited money comes from and withdrawn money goes to
+    Funding = 1,
+    /// Where fees are paid to
+    FeeRevenue = 2,
+    /// Counterpart of admin corrections to users' balances
+    Adjustment = 3,
+}
+
+/// What caused a journal entry in the cash ledger, the kind of a wallet
+/// transaction
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EntryType {
+    Deposit = 0,
+    Trade = 1,
+    Fee = 2,
+    Withdrawal = 3,
+    /// Correction made by an admin
+    Adjustment = 4,
+}
+
+impl From<i64> for EntryType {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => EntryType::Deposit,
+            1 => EntryType::Trade,
+            2 => EntryType::Fee,
+            3 => EntryType::Withdrawal,
+            4 => EntryType::Adjustment,
+            _ => unreachable!("Invalid i64 value for EntryType"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Dummy, PartialEq)]
@@ -85,6 +318,32 @@ pub struct StockTransaction {
     pub stock_price: i64,
     #[dummy(faker = "1..200")]
     pub quantity: i64,
+    pub time_in_force: TimeInForce,
+    pub expiry_reason: Option<ExpiryReason>,
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Slice size of an iceberg order
+    pub display_quantity: Option<i64>,
+    /// Set on the legs of an OCO pair or bracket, the later legs point back
+    /// at the first one through `parent_stock_tx_id`
+    pub order_group: Option<OrderGroupType>,
+    pub trigger_price: Option<i64>,
+    /// When a stop order was triggered and turned into the order whose
+    /// `parent_stock_tx_id` points back at it
+    pub triggered_at: Option<DateTime<Utc>>,
+    /// Changes made to the order through `/engine/modifyStockOrder`, oldest
+    /// first
+    pub amendments: Vec<OrderAmendment>,
+    pub time_stamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq)]
+pub struct OrderAmendment {
+    pub previous_quantity: i64,
+    pub previous_price: i64,
+    pub quantity: i64,
+    pub price: i64,
+    /// Whether the order kept its place in the queue
+    pub kept_priority: bool,
     pub time_stamp: DateTime<Utc>,
 }
 
@@ -95,6 +354,43 @@ fn success<T: Serialize>(input: &T) -> String {
     json!({ "success": true, "data": input }).to_string()
 }
 
+/// One page of a user's history, `next_cursor` is passed back as `after`
+/// for the page that follows it and is null on the last page. It sits next
+/// to `data` so that clients not paging still read a plain list
+#[derive(Debug)]
+pub struct Page<T> {
+    pub data: T,
+    pub next_cursor: Option<String>,
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    #[tracing::instrument(skip_all)]
+    fn into_response(self) -> Response {
+        (
+            StatusCode::OK,
+            json!({ "success": true, "data": self.data, "next_cursor": self.next_cursor })
+                .to_string(),
+        )
+            .into_response()
+    }
+}
+
+/// Narrows down a user's stock or wallet transactions. Wallet transactions
+/// match the order filters through the order of their trade or fee
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    pub stock_id: Option<i64>,
+    pub side: Option<Side>,
+    pub order_status: Option<OrderStatus>,
+    pub order_type: Option<OrderType>,
+    /// Only applies to wallet transactions
+    pub kind: Option<EntryType>,
+    /// Inclusive
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub to: Option<DateTime<Utc>>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EmptyResponse {}
 
@@ -139,9 +435,24 @@ impl_into_response!(StockPriceVec);
 pub struct StockPortfolioVec(pub Vec<StockPortfolio>);
 impl_into_response!(StockPortfolioVec);
 
+/// Portfolio totals across every stock a user traded, closed positions
+/// included. Stocks that haven't traded yet are left out of `market_value`
+/// and `unrealized_pnl`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PortfolioSummary {
+    pub cost_basis: i64,
+    pub market_value: i64,
+    pub unrealized_pnl: i64,
+    pub realized_pnl: i64,
+}
+impl_into_response!(PortfolioSummary);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Balance {
     pub balance: i64,
+    /// Part of `balance` not held for open buy orders or pending withdrawals
+    pub available: i64,
+    pub held: i64,
 }
 impl_into_response!(Balance);
 
@@ -149,10 +460,294 @@ impl_into_response!(Balance);
 pub struct WalletVec(pub Vec<WalletTransaction>);
 impl_into_response!(WalletVec);
 
+#[derive(Serialize, Deserialize, Debug, Dummy, PartialEq, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WithdrawalStatus {
+    /// Waiting for an admin, with the amount held
+    Pending = 0,
+    Approved = 1,
+    Rejected = 2,
+}
+
+impl From<i64> for WithdrawalStatus {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => WithdrawalStatus::Pending,
+            1 => WithdrawalStatus::Approved,
+            2 => WithdrawalStatus::Rejected,
+            _ => unreachable!("Invalid i64 value for WithdrawalStatus"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Dummy)]
+pub struct Withdrawal {
+    pub withdrawal_id: String,
+    pub user_name: String,
+    #[dummy(faker = "1..10000")]
+    pub amount: i64,
+    pub status: WithdrawalStatus,
+    pub time_stamp: DateTime<Utc>,
+}
+impl_into_response!(Withdrawal);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WithdrawalVec(pub Vec<Withdrawal>);
+impl_into_response!(WithdrawalVec);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TradeVec(pub Vec<StockTransaction>);
 impl_into_response!(TradeVec);
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// An anonymised execution on the public trade tape
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MarketTrade {
+    /// Increases by one with every print in the stock
+    pub seq: i64,
+    pub price: i64,
+    pub quantity: i64,
+    /// Side of the incoming order that took liquidity, `None` for auction
+    /// prints
+    pub aggressor: Option<Side>,
+    pub time_stamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MarketTradeVec(pub Vec<MarketTrade>);
+impl_into_response!(MarketTradeVec);
+
+/// Candle width, the discriminant is its length in seconds
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute = 60,
+    #[serde(rename = "5m")]
+    FiveMinutes = 300,
+    #[serde(rename = "1h")]
+    OneHour = 3600,
+    #[serde(rename = "1d")]
+    OneDay = 86400,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 4] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+}
+
+/// OHLCV bar starting at `time_stamp`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Candle {
+    pub time_stamp: DateTime<Utc>,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CandleVec(pub Vec<Candle>);
+impl_into_response!(CandleVec);
+
+/// Aggregated resting volume at one price
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PriceLevel {
+    pub price: i64,
+    pub quantity: i64,
+    pub order_count: i64,
+}
+
+/// Level 2 view of a stock's book, best price first on both sides
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OrderBookDepth {
+    pub stock_id: String,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+impl_into_response!(OrderBookDepth);
+
+/// Trading session phase of a stock
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TradingPhase {
+    /// Orders match as they arrive
+    #[default]
+    Continuous = 0,
+    /// Call phase where orders collect until the opening auction
+    PreOpen = 1,
+    /// Call phase ending in the closing auction, which sets the official
+    /// close
+    PreClose = 2,
+}
+
+impl TradingPhase {
+    /// Whether orders collect without matching
+    pub fn is_call(self) -> bool {
+        self != TradingPhase::Continuous
+    }
+}
+
+impl From<i64> for TradingPhase {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => TradingPhase::Continuous,
+            1 => TradingPhase::PreOpen,
+            2 => TradingPhase::PreClose,
+            _ => unreachable!("Invalid i64 value for TradingPhase"),
+        }
+    }
+}
+
+/// Fees charged on trades to the accounts of a tier
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeeSchedule {
+    pub tier: String,
+    /// Rate for the resting side of a trade, in basis points of its notional
+    pub maker_bps: i64,
+    /// Rate for the incoming side of a trade, in basis points of its notional
+    pub taker_bps: i64,
+    /// Least charged per trade
+    pub min_fee: Option<i64>,
+}
+
+impl FeeSchedule {
+    pub fn is_valid(&self) -> bool {
+        !self.tier.is_empty()
+            && self.maker_bps >= 0
+            && self.taker_bps >= 0
+            && self.min_fee.is_none_or(|min| min >= 0)
+    }
+
+    /// Fee on one trade, the same as the database books
+    pub fn fee(&self, notional: i64, is_maker: bool) -> i64 {
+        let bps = if is_maker {
+            self.maker_bps
+        } else {
+            self.taker_bps
+        };
+        (notional * bps / 10_000).max(self.min_fee.unwrap_or(0))
+    }
+
+    /// Fee held for an order that could trade either way, assuming it fills
+    /// in one go
+    pub fn max_fee(&self, notional: i64) -> i64 {
+        self.fee(notional, true).max(self.fee(notional, false))
+    }
+}
+
+/// Trading parameters of a stock its orders are checked against
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(default)]
+pub struct TradingRules {
+    /// Prices have to be a multiple of this
+    pub tick_size: i64,
+    /// Quantities have to be a multiple of this
+    pub lot_size: i64,
+    pub max_order_quantity: Option<i64>,
+    /// Furthest a limit price may be from the last trade, in percent of it
+    pub price_collar_percent: Option<i64>,
+}
+
+impl Default for TradingRules {
+    fn default() -> Self {
+        TradingRules {
+            tick_size: 1,
+            lot_size: 1,
+            max_order_quantity: None,
+            price_collar_percent: None,
+        }
+    }
+}
+
+impl TradingRules {
+    pub fn is_valid(&self) -> bool {
+        self.tick_size > 0
+            && self.lot_size > 0
+            && self
+                .max_order_quantity
+                .is_none_or(|max| max >= self.lot_size)
+            && self.price_collar_percent.is_none_or(|pct| pct > 0)
+    }
+
+    pub fn check_price(&self, price: i64) -> Result<(), AppError> {
+        if price <= 0 || price % self.tick_size != 0 {
+            return Err(AppError::OrderRejected(
+                "Price must be a positive multiple of the tick size",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_quantity(&self, quantity: i64) -> Result<(), AppError> {
+        if quantity <= 0 || quantity % self.lot_size != 0 {
+            return Err(AppError::OrderRejected(
+                "Quantity must be a positive multiple of the lot size",
+            ));
+        }
+        if self.max_order_quantity.is_some_and(|max| quantity > max) {
+            return Err(AppError::OrderRejected(
+                "Quantity above the maximum order quantity",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check a limit price that can trade straight away against the collar
+    /// around `last_price`, there is none before the first trade
+    pub fn check_collar(&self, price: i64, last_price: Option<i64>) -> Result<(), AppError> {
+        if let (Some(pct), Some(last)) = (self.price_collar_percent, last_price)
+            && (price - last).abs() * 100 > pct * last
+        {
+            return Err(AppError::OrderRejected(
+                "Price outside the collar around the last trade",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Halts a stock for `halt_secs` once its prints within the last
+/// `window_secs` are more than `max_move_percent` apart, measured against
+/// the lowest of them
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct CircuitBreaker {
+    pub max_move_percent: i64,
+    pub window_secs: i64,
+    pub halt_secs: i64,
+}
+
+impl CircuitBreaker {
+    pub fn is_valid(&self) -> bool {
+        self.max_move_percent > 0 && self.window_secs > 0 && self.halt_secs > 0
+    }
+}
+
+/// What the auction of a stock would do if it uncrossed now
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AuctionIndicative {
+    pub stock_id: String,
+    pub phase: TradingPhase,
+    /// `None` while nothing crosses
+    pub indicative_price: Option<i64>,
+    pub matched_volume: i64,
+    /// Quantity left unmatched at the indicative price
+    pub imbalance: i64,
+    /// Side the unmatched quantity is on
+    pub imbalance_side: Option<Side>,
+}
+impl_into_response!(AuctionIndicative);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StockId {
     pub stock_id: String,
@@ -166,8 +761,18 @@ pub enum AppError {
     PasswordInvalid,
     AuthTokenInvalid,
     AuthTokenNotPresent,
+    /// Endpoint only open to admins
+    Forbidden,
     StockNotFound,
     StockTransactionNotFound,
+    /// No pending withdrawal with the id
+    WithdrawalNotFound,
+    InsufficientFunds,
+    InsufficientShares,
+    /// Orders can't be placed or amended while the stock is halted
+    TradingHalted,
+    /// Order breaking the trading rules of its stock, with the rule broken
+    OrderRejected(&'static str),
     BadRequest,
     /// Generic DB error that is irrecoverable. Required: `error!()`
     DatabaseError,
@@ -199,11 +804,19 @@ impl IntoResponse for AppError {
                 StatusCode::UNAUTHORIZED,
                 error("Authorization token not valid"),
             ),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, error("Admin only")),
             AppError::StockNotFound => (StatusCode::BAD_REQUEST, error("Stock not found")),
             AppError::StockTransactionNotFound => (
                 StatusCode::BAD_REQUEST,
                 error("Stock transaction not found"),
             ),
+            AppError::WithdrawalNotFound => {
+                (StatusCode::BAD_REQUEST, error("Withdrawal not found"))
+            }
+            AppError::InsufficientFunds => (StatusCode::BAD_REQUEST, error("Insufficient funds")),
+            AppError::InsufficientShares => (StatusCode::BAD_REQUEST, error("Insufficient shares")),
+            AppError::TradingHalted => (StatusCode::CONFLICT, error("Trading halted")),
+            AppError::OrderRejected(reason) => (StatusCode::BAD_REQUEST, error(reason)),
             AppError::DatabaseError => (StatusCode::INTERNAL_SERVER_ERROR, error("")),
             AppError::InternalServerError => (StatusCode::INTERNAL_SERVER_ERROR, error("")),
         }